@@ -1,5 +1,21 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use id_pool::IdPool;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use id_pool::{BTreeIdPool, IdPool, Range, SyncIdPool};
+
+// mirrors the id type selected for the library
+#[cfg(feature = "u16")]
+type Num = u16;
+#[cfg(feature = "u32")]
+type Num = u32;
+#[cfg(feature = "u64")]
+type Num = u64;
+#[cfg(feature = "usize")]
+type Num = usize;
+
+/// Caps a pool size at what fits in `Num`.
+#[allow(clippy::unnecessary_cast)]
+fn clamp(n: u64) -> Num {
+    n.min(Num::MAX as u64) as Num
+}
 
 pub fn request(c: &mut Criterion) {
     let mut pool = IdPool::new();
@@ -10,17 +26,178 @@ pub fn request_return(c: &mut Criterion) {
     let mut pool = IdPool::new();
     c.bench_function("request_return", |b| {
         b.iter(|| {
-            black_box({
-                pool.request_id();
-                pool.return_id(0).unwrap();
-            })
+            let id = black_box(pool.request_id()).unwrap();
+            pool.return_id(id).unwrap();
         })
     });
 }
 
+pub fn available(c: &mut Criterion) {
+    let mut group = c.benchmark_group("available");
+    // same fragmentation over ranges of vastly different widths
+    for &end in &[1_000, clamp(1_000_000_000)] {
+        let mut pool = IdPool::new_ranged(1..end);
+        for _ in 0..200 {
+            pool.request_id();
+        }
+        for id in (1..200).step_by(2) {
+            pool.return_id(id).unwrap();
+        }
+        group.bench_function(format!("width_{}", end), |b| {
+            b.iter(|| black_box(pool.available()))
+        });
+    }
+    group.finish();
+}
+
+pub fn compact(c: &mut Criterion) {
+    let end = clamp(100_000);
+    let mut pool = IdPool::new_ranged(1..end).with_lazy_coalescing(true);
+    for _ in 0..end / 2 {
+        pool.request_id();
+    }
+    // return runs of two ids, each left as two adjacent ranges
+    for id in (1..end / 2).filter(|id| id % 3 != 0) {
+        pool.return_id(id).unwrap();
+    }
+    let mut group = c.benchmark_group("compact");
+    group.bench_function("in_place", |b| {
+        b.iter_batched(
+            || pool.clone(),
+            |mut pool| {
+                pool.compact_ranges_in_place();
+                pool
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("naive_rebuild", |b| {
+        b.iter_batched(
+            || pool.clone(),
+            |pool| {
+                let mut merged: Vec<std::ops::Range<Num>> = Vec::new();
+                for range in pool.free_ranges() {
+                    match merged.last_mut() {
+                        Some(last) if last.end == range.start => last.end = range.end,
+                        _ => merged.push(range),
+                    }
+                }
+                let free = merged.into_iter().rev().map(Range::from).collect();
+                IdPool::from_parts(1..end, free, pool.used_count()).unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+pub fn batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch_10k");
+    group.bench_function("request_ids", |b| {
+        b.iter_batched(
+            IdPool::new,
+            |mut pool| black_box(pool.request_ids(10_000)),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("request_id", |b| {
+        b.iter_batched(
+            IdPool::new,
+            |mut pool| black_box((0..10_000).map(|_| pool.request_id()).collect::<Vec<_>>()),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+pub fn random_returns(c: &mut Criterion) {
+    // every other id free, then each return bridges two ranges
+    let count = clamp(200_000);
+    let order: Vec<Num> = {
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut ids: Vec<Num> = (1..count).step_by(2).collect();
+        for i in (1..ids.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            ids.swap(i, (state % (i as u64 + 1)) as usize);
+        }
+        ids
+    };
+    let mut vec_pool = IdPool::new_ranged(0..count);
+    let mut btree_pool = BTreeIdPool::new_ranged(0..count);
+    for _ in 0..count {
+        vec_pool.request_id();
+        btree_pool.request_id();
+    }
+    for id in (0..count).step_by(2) {
+        vec_pool.return_id(id).unwrap();
+        btree_pool.return_id(id).unwrap();
+    }
+    let mut group = c.benchmark_group("random_returns");
+    group.sample_size(10);
+    group.bench_function("vec", |b| {
+        b.iter_batched(
+            || vec_pool.clone(),
+            |mut pool| {
+                for &id in &order {
+                    pool.return_id(id).unwrap();
+                }
+                pool
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("btree", |b| {
+        b.iter_batched(
+            || btree_pool.clone(),
+            |mut pool| {
+                for &id in &order {
+                    pool.return_id(id).unwrap();
+                }
+                pool
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+pub fn contention(c: &mut Criterion) {
+    const THREADS: usize = 8;
+    let mut group = c.benchmark_group("contention");
+    for &shards in &[1, 8] {
+        let pool = SyncIdPool::with_shards(0..clamp(100_000), shards);
+        group.bench_function(format!("{}_shards", shards), |b| {
+            b.iter(|| {
+                std::thread::scope(|scope| {
+                    for _ in 0..THREADS {
+                        scope.spawn(|| {
+                            for _ in 0..10_000 {
+                                let id = pool.request_id().unwrap();
+                                pool.return_id(black_box(id)).unwrap();
+                            }
+                        });
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
 // pub fn random(c: &mut Criterion) {
 //     c.bench_function("random", |b| b.iter(|| fibonacci(black_box(20))));
 // }
 
-criterion_group!(benches, request, request_return);
+criterion_group!(
+    benches,
+    request,
+    request_return,
+    available,
+    compact,
+    batch,
+    random_returns,
+    contention
+);
 criterion_main!(benches);
@@ -0,0 +1,101 @@
+use std::sync::Mutex;
+
+use tokio::sync::Notify;
+
+use crate::{IdPool, Num};
+
+/// Id pool for async contexts, where requests wait for an id
+/// to become available instead of failing when the pool is
+/// exhausted.
+///
+/// The pool can be shared between tasks, e.g. by wrapping it
+/// in an `Arc`.
+#[derive(Debug, Default)]
+pub struct AsyncIdPool {
+    pool: Mutex<IdPool>,
+    /// Wakes up tasks waiting for an id to be returned
+    returned: Notify,
+}
+
+impl AsyncIdPool {
+    /// Creates a new `AsyncIdPool` with a default range, which
+    /// starts at `1` and ends at `Num::MAX`.
+    pub fn new() -> Self {
+        Self::new_ranged(1..Num::MAX)
+    }
+
+    /// Creates a new `AsyncIdPool` with the given range.
+    pub fn new_ranged(range: std::ops::Range<Num>) -> Self {
+        Self {
+            pool: Mutex::new(IdPool::new_ranged(range)),
+            returned: Notify::new(),
+        }
+    }
+
+    /// Gets the current count of used ids.
+    pub fn used_count(&self) -> usize {
+        self.pool.lock().unwrap().used_count()
+    }
+
+    /// Returns a new id, waiting for one to be returned to the
+    /// pool if there are no free ids.
+    pub async fn request_id(&self) -> Num {
+        loop {
+            // register interest before checking the pool so that
+            // a return in between isn't missed
+            let returned = self.returned.notified();
+            tokio::pin!(returned);
+            returned.as_mut().enable();
+            if let Some(id) = self.try_request_id() {
+                return id;
+            }
+            returned.await;
+        }
+    }
+
+    /// Returns a new id or `None` if there are no free ids in
+    /// the pool, without waiting.
+    pub fn try_request_id(&self) -> Option<Num> {
+        self.pool.lock().unwrap().request_id()
+    }
+
+    /// Returns an id to the pool, waking up a task waiting for
+    /// an id if there is one. Returns `Err(Num)` if the id is
+    /// already in the pool.
+    pub fn return_id(&self, id: Num) -> Result<(), Num> {
+        self.pool.lock().unwrap().return_id(id)?;
+        self.returned.notify_one();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn return_wakes_waiting_request() {
+        let pool = Arc::new(AsyncIdPool::new_ranged(1..3));
+        assert_eq!(1, pool.request_id().await);
+        assert_eq!(2, pool.request_id().await);
+        assert_eq!(None, pool.try_request_id());
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.request_id().await }
+        });
+        // let the spawned task park on the exhausted pool
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.return_id(2) }
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(2, waiting.await.unwrap());
+        assert_eq!(2, pool.used_count());
+    }
+}
@@ -0,0 +1,162 @@
+use crate::{BTreeIdPool, IdPool, Num};
+
+/// Core interface shared by the pools handing out the lowest
+/// free id first, whatever the structure storing their free
+/// ranges.
+///
+/// Both [`IdPool`] and [`BTreeIdPool`] implement it, so code
+/// generic over `PoolBackend` can pick whichever suits the
+/// expected fragmentation without being rewritten. Only the
+/// basic operations are covered, since [`BTreeIdPool`] lacks
+/// most of the rest of the [`IdPool`] API.
+///
+/// # Examples
+///
+/// ```
+/// # use id_pool::{BTreeIdPool, IdPool, PoolBackend};
+/// fn drain<P: PoolBackend>(pool: &mut P) -> usize {
+///     while pool.request_id().is_some() {}
+///     pool.used_count()
+/// }
+/// assert_eq!(5, drain(&mut IdPool::new_ranged(0..5)));
+/// assert_eq!(5, drain(&mut BTreeIdPool::new_ranged(0..5)));
+/// ```
+///
+/// [`IdPool`]: struct.IdPool.html
+/// [`BTreeIdPool`]: struct.BTreeIdPool.html
+pub trait PoolBackend {
+    /// Returns a new id or `None` if there are no free ids in
+    /// the pool.
+    fn request_id(&mut self) -> Option<Num>;
+
+    /// Returns an id to the pool or `Err(Num)` if the id is
+    /// already in the pool or lies outside of the configured
+    /// range.
+    fn return_id(&mut self, id: Num) -> Result<(), Num>;
+
+    /// Checks whether the given id is currently free.
+    fn is_free(&self, id: Num) -> bool;
+
+    /// Gets the current count of used ids.
+    fn used_count(&self) -> usize;
+
+    /// Gets the current count of free ids.
+    fn available(&self) -> Num;
+
+    /// Gets the current count of free ranges.
+    fn free_range_count(&self) -> usize;
+
+    /// Returns an iterator over all the free ranges, in
+    /// ascending order.
+    fn free_ranges(&self) -> Box<dyn Iterator<Item = std::ops::Range<Num>> + '_>;
+
+    /// Gets the lowest free id, which is the id the next call to
+    /// `request_id` returns, or `None` if there are no free ids.
+    fn lowest_available(&self) -> Option<Num> {
+        self.free_ranges().next().map(|range| range.start)
+    }
+
+    /// Returns `count` new ids or `None` if there are not enough
+    /// free ids in the pool, in which case none are allocated.
+    fn request_ids(&mut self, count: usize) -> Option<Vec<Num>> {
+        if (self.available() as usize) < count {
+            return None;
+        }
+        (0..count).map(|_| self.request_id()).collect()
+    }
+}
+
+impl PoolBackend for IdPool {
+    fn request_id(&mut self) -> Option<Num> {
+        IdPool::request_id(self)
+    }
+
+    fn return_id(&mut self, id: Num) -> Result<(), Num> {
+        IdPool::return_id(self, id)
+    }
+
+    fn is_free(&self, id: Num) -> bool {
+        IdPool::is_free(self, id)
+    }
+
+    fn used_count(&self) -> usize {
+        IdPool::used_count(self)
+    }
+
+    fn available(&self) -> Num {
+        IdPool::available(self)
+    }
+
+    fn free_range_count(&self) -> usize {
+        IdPool::free_range_count(self)
+    }
+
+    fn free_ranges(&self) -> Box<dyn Iterator<Item = std::ops::Range<Num>> + '_> {
+        Box::new(IdPool::free_ranges(self))
+    }
+
+    fn lowest_available(&self) -> Option<Num> {
+        IdPool::lowest_available(self)
+    }
+
+    fn request_ids(&mut self, count: usize) -> Option<Vec<Num>> {
+        IdPool::request_ids(self, count)
+    }
+}
+
+impl PoolBackend for BTreeIdPool {
+    fn request_id(&mut self) -> Option<Num> {
+        BTreeIdPool::request_id(self)
+    }
+
+    fn return_id(&mut self, id: Num) -> Result<(), Num> {
+        BTreeIdPool::return_id(self, id)
+    }
+
+    fn is_free(&self, id: Num) -> bool {
+        BTreeIdPool::is_free(self, id)
+    }
+
+    fn used_count(&self) -> usize {
+        BTreeIdPool::used_count(self)
+    }
+
+    fn available(&self) -> Num {
+        BTreeIdPool::available(self)
+    }
+
+    fn free_range_count(&self) -> usize {
+        BTreeIdPool::free_range_count(self)
+    }
+
+    fn free_ranges(&self) -> Box<dyn Iterator<Item = std::ops::Range<Num>> + '_> {
+        Box::new(BTreeIdPool::free_ranges(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exercise<P: PoolBackend>(mut pool: P) -> (Vec<Num>, Vec<std::ops::Range<Num>>) {
+        let mut ids = pool.request_ids(6).unwrap();
+        assert_eq!(None, pool.request_ids(5));
+        assert_eq!(Ok(()), pool.return_id(1));
+        assert_eq!(Ok(()), pool.return_id(3));
+        assert_eq!(Err(3), pool.return_id(3));
+        assert!(pool.is_free(1) && !pool.is_free(2));
+        assert_eq!(Some(1), pool.lowest_available());
+        ids.extend(pool.request_id());
+        assert_eq!(5, pool.used_count());
+        assert_eq!(3, pool.available());
+        assert_eq!(2, pool.free_range_count());
+        (ids, pool.free_ranges().collect())
+    }
+
+    #[test]
+    fn backends_agree() {
+        let vec = exercise(IdPool::new_ranged(0..8));
+        assert_eq!(vec, exercise(BTreeIdPool::new_ranged(0..8)));
+        assert_eq!(vec![3..4, 6..8], vec.1);
+    }
+}
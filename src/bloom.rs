@@ -0,0 +1,129 @@
+use crate::{mix, IdPool, Num};
+
+/// Number of free ids `request_id_not_in_bloom` checks against
+/// the filter before giving up.
+const MAX_PROBES: usize = 1024;
+
+/// Bloom filter over ids, for approximately tracking ids that
+/// are in use elsewhere, e.g. on other nodes.
+///
+/// The filter never misses an inserted id, but may report ids
+/// that were never inserted, at a rate depending on its size
+/// and number of hash functions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    /// Number of bits in the filter
+    len: u64,
+    /// Number of hash functions
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Creates an empty `BloomFilter` of `bits` bits, setting
+    /// `hashes` bits for every inserted id.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bits` or `hashes` is zero.
+    pub fn new(bits: usize, hashes: u32) -> Self {
+        assert!(bits != 0 && hashes != 0, "bloom filter must be non-empty");
+        Self {
+            bits: vec![0; bits.div_ceil(64)],
+            len: bits as u64,
+            hashes,
+        }
+    }
+
+    /// Adds an id to the filter.
+    pub fn insert(&mut self, id: Num) {
+        for bit in self.bit_indices(id) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Checks whether the id may have been added to the filter.
+    /// Returns `false` only if it definitely wasn't.
+    pub fn might_contain(&self, id: Num) -> bool {
+        self.bit_indices(id)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Gets the bits set for the id, derived from two hashes by
+    /// double hashing.
+    fn bit_indices(&self, id: Num) -> impl Iterator<Item = u64> {
+        let h1 = mix(id as u64);
+        // odd, so that all bits can be reached
+        let h2 = mix(h1) | 1;
+        let len = self.len;
+        (0..self.hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % len)
+    }
+}
+
+impl IdPool {
+    /// Returns the lowest free id the filter doesn't report as
+    /// possibly in use, or `None` if there is no such id.
+    ///
+    /// Ids rejected by the filter are skipped but stay free,
+    /// including false positives. This makes collisions with
+    /// ids allocated elsewhere unlikely without exact
+    /// coordination.
+    ///
+    /// The search gives up with `None` after checking 1024 free
+    /// ids, so that a saturated filter doesn't turn the request
+    /// into a scan over the whole pool.
+    pub fn request_id_not_in_bloom(&mut self, filter: &BloomFilter) -> Option<Num> {
+        if !self.can_allocate(1) {
+            return None;
+        }
+        let ceiling = self.ceiling;
+        // ranges are in descending order, walk them from the lowest
+        let id = self
+            .free
+            .iter()
+            .rev()
+            .flat_map(|range| range.start..range.end)
+            .take_while(|&id| !matches!(ceiling, Some(ceiling) if id >= ceiling))
+            .take(MAX_PROBES)
+            .find(|&id| !filter.might_contain(id))?;
+        self.allocate(id);
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_in_bloom() {
+        let mut filter = BloomFilter::new(1024, 3);
+        for id in 0..4 {
+            filter.insert(id);
+        }
+        assert!((0..4).all(|id| filter.might_contain(id)));
+        let mut pool = IdPool::new_ranged(0..100);
+        let first = (4..100).find(|&id| !filter.might_contain(id)).unwrap();
+        assert_eq!(Some(first), pool.request_id_not_in_bloom(&filter));
+        // skipped ids stay free
+        assert_eq!(Some(0), pool.request_id());
+        assert_eq!(2, pool.used_count());
+        pool.assert_consistent();
+
+        let mut pool = IdPool::new_ranged(0..4);
+        assert_eq!(None, pool.request_id_not_in_bloom(&filter));
+        assert_eq!(0, pool.used_count());
+    }
+
+    #[test]
+    fn saturated_bloom() {
+        let mut filter = BloomFilter::new(64, 1);
+        for id in 0..1000 {
+            filter.insert(id);
+        }
+        assert!(filter.bits.iter().all(|&word| word == u64::MAX));
+        let mut pool = IdPool::new_ranged(0..Num::MAX);
+        assert_eq!(None, pool.request_id_not_in_bloom(&filter));
+        assert_eq!(0, pool.used_count());
+    }
+}
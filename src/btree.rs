@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+
+use crate::{IdPoolError, Num};
+
+/// Alternative pool type storing its free ranges in a
+/// `BTreeMap`, keyed by range start.
+///
+/// Like [`IdPool`] it hands out the lowest free id first, but
+/// returning an id takes logarithmic rather than linear time in
+/// the number of free ranges. This pays off for heavily
+/// fragmented pools with many thousands of ranges, while
+/// [`IdPool`] is faster for the common case of few ranges.
+///
+/// It's a separate type rather than a storage option of
+/// [`IdPool`], and only covers requesting, returning and
+/// inspecting ids. Blocking, allocation ceilings, earmarks and
+/// serialization are not supported. Code written against
+/// [`PoolBackend`], which both types implement, works with
+/// either of them.
+///
+/// [`IdPool`]: struct.IdPool.html
+/// [`PoolBackend`]: trait.PoolBackend.html
+///
+/// # Examples
+///
+/// ```
+/// # use id_pool::BTreeIdPool;
+/// let mut pool = BTreeIdPool::new();
+/// assert_eq!(Some(1), pool.request_id());
+/// assert_eq!(Some(2), pool.request_id());
+/// assert_eq!(Ok(()), pool.return_id(1));
+/// assert_eq!(Some(1), pool.request_id());
+/// assert_eq!(Some(3), pool.request_id());
+/// ```
+#[derive(Debug, Clone)]
+pub struct BTreeIdPool {
+    /// Configured range of ids
+    range: std::ops::Range<Num>,
+    /// Free ranges, mapping range start to range end
+    free: BTreeMap<Num, Num>,
+    /// Number of ids currently in use
+    used: usize,
+}
+
+impl Default for BTreeIdPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BTreeIdPool {
+    /// Creates a new `BTreeIdPool` with a default range, which
+    /// starts at `1` and ends at `Num::MAX`.
+    pub fn new() -> Self {
+        Self::new_ranged(1..Num::MAX)
+    }
+
+    /// Creates a new `BTreeIdPool` with the given range.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range start is greater than the range end.
+    pub fn new_ranged(range: std::ops::Range<Num>) -> Self {
+        assert!(range.start <= range.end, "{}", IdPoolError::InvalidRange);
+        let mut free = BTreeMap::new();
+        if !range.is_empty() {
+            free.insert(range.start, range.end);
+        }
+        Self {
+            range,
+            free,
+            used: 0,
+        }
+    }
+
+    /// Gets the current count of used ids.
+    pub fn used_count(&self) -> usize {
+        self.used
+    }
+
+    /// Gets the current count of free ids.
+    pub fn available(&self) -> Num {
+        self.free.iter().map(|(start, end)| end - start).sum()
+    }
+
+    /// Gets the current count of free ranges.
+    pub fn free_range_count(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Returns an iterator over all the free ranges, in
+    /// ascending order.
+    pub fn free_ranges(&self) -> impl Iterator<Item = std::ops::Range<Num>> + '_ {
+        self.free.iter().map(|(&start, &end)| start..end)
+    }
+
+    /// Checks whether the given id is currently free.
+    pub fn is_free(&self, id: Num) -> bool {
+        matches!(self.free.range(..=id).next_back(), Some((_, &end)) if end > id)
+    }
+
+    /// Returns a new id or `None` if there are no free ids
+    /// in the pool.
+    pub fn request_id(&mut self) -> Option<Num> {
+        let (&start, &end) = self.free.iter().next()?;
+        self.free.remove(&start);
+        if start + 1 < end {
+            self.free.insert(start + 1, end);
+        }
+        self.used += 1;
+        Some(start)
+    }
+
+    /// Returns an id to the pool or `Err(Num)` if the id is
+    /// already in the pool or lies outside of the configured
+    /// range.
+    pub fn return_id(&mut self, id: Num) -> Result<(), Num> {
+        if !self.range.contains(&id) || self.is_free(id) {
+            return Err(id);
+        }
+        // ids lie below the end of the range, so this can't overflow
+        let mut start = id;
+        let mut end = id + 1;
+        // merge with the range ending right at the id
+        if let Some((&below, _)) = self.free.range(..id).next_back().filter(|(_, &e)| e == id) {
+            self.free.remove(&below);
+            start = below;
+        }
+        // merge with the range starting right after the id
+        if let Some(above) = self.free.remove(&end) {
+            end = above;
+        }
+        self.free.insert(start, end);
+        self.used -= 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::XorShift;
+    use crate::IdPool;
+
+    #[test]
+    fn matches_vec_backend() {
+        let mut rng = XorShift::new(0x9e37_79b9_7f4a_7c15);
+        let mut next = move || rng.next_u64();
+        let mut vec_pool = IdPool::new_ranged(0..200);
+        let mut btree_pool = BTreeIdPool::new_ranged(0..200);
+        for _ in 0..20_000 {
+            let id = (next() % 210) as Num;
+            if next() % 2 == 0 {
+                assert_eq!(vec_pool.request_id(), btree_pool.request_id());
+            } else {
+                assert_eq!(vec_pool.return_id(id), btree_pool.return_id(id));
+            }
+            assert_eq!(vec_pool.used_count(), btree_pool.used_count());
+        }
+        assert!(vec_pool.free_ranges().eq(btree_pool.free_ranges()));
+        assert_eq!(vec_pool.available(), btree_pool.available());
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crossbeam_channel::Receiver;
+
+use crate::{Num, SyncIdPool};
+
+/// Source of ids pre-allocated from a [`SyncIdPool`] by a
+/// background thread, which keeps a bounded buffer of ids
+/// topped up.
+///
+/// Requesting an id is a plain receive from the buffer, so
+/// the hot path never contends on the pool's lock. Returned
+/// ids go straight back to the pool. Dropping the source stops
+/// the background thread and returns all the buffered ids.
+///
+/// [`SyncIdPool`]: struct.SyncIdPool.html
+#[derive(Debug)]
+pub struct BufferedIdSource {
+    pool: Arc<SyncIdPool>,
+    ids: Receiver<Num>,
+    stop: Arc<AtomicBool>,
+    refill: Option<JoinHandle<()>>,
+}
+
+impl BufferedIdSource {
+    /// Creates a new source buffering up to `capacity` ids
+    /// requested from `pool`.
+    pub fn new(pool: Arc<SyncIdPool>, capacity: usize) -> Self {
+        let (sender, ids) = crossbeam_channel::bounded(capacity);
+        let stop = Arc::new(AtomicBool::new(false));
+        let refill = thread::spawn({
+            let pool = pool.clone();
+            let stop = stop.clone();
+            move || {
+                while let Some(id) = pool.wait_request_id(&stop) {
+                    if let Err(e) = sender.send(id) {
+                        let _ = pool.return_id(e.into_inner());
+                        break;
+                    }
+                }
+            }
+        });
+        Self {
+            pool,
+            ids,
+            stop,
+            refill: Some(refill),
+        }
+    }
+
+    /// Returns a new id, waiting for the buffer to be refilled
+    /// if it's empty.
+    pub fn request_id(&self) -> Num {
+        // the refilling thread only exits once the source is dropped
+        self.ids.recv().expect("refill thread exited")
+    }
+
+    /// Returns a new id or `None` if the buffer is currently
+    /// empty, without waiting.
+    pub fn try_request_id(&self) -> Option<Num> {
+        self.ids.try_recv().ok()
+    }
+
+    /// Returns an id to the underlying pool or `Err(Num)` if
+    /// the id is already in the pool.
+    pub fn return_id(&self, id: Num) -> Result<(), Num> {
+        self.pool.return_id(id)
+    }
+
+    /// Gets the underlying pool.
+    pub fn pool(&self) -> &Arc<SyncIdPool> {
+        &self.pool
+    }
+}
+
+impl Drop for BufferedIdSource {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.pool.wake_all();
+        // receiving unblocks a pending send, the channel gets
+        // disconnected once the refilling thread notices the flag
+        for id in self.ids.iter() {
+            let _ = self.pool.return_id(id);
+        }
+        if let Some(refill) = self.refill.take() {
+            let _ = refill.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn unique_ids() {
+        let pool = Arc::new(SyncIdPool::new_ranged(0..8));
+        let source = BufferedIdSource::new(pool.clone(), 4);
+        let mut ids = HashSet::new();
+        for _ in 0..8 {
+            assert!(ids.insert(source.request_id()));
+        }
+        assert_eq!(None, source.try_request_id());
+        // returned ids are buffered again by the refilling thread
+        for &id in &ids {
+            assert_eq!(Ok(()), source.return_id(id));
+        }
+        let mut again = HashSet::new();
+        for _ in 0..8 {
+            assert!(again.insert(source.request_id()));
+        }
+        assert_eq!(ids, again);
+        for &id in &again {
+            assert_eq!(Ok(()), source.return_id(id));
+        }
+        drop(source);
+        assert_eq!(0, pool.used_count());
+    }
+}
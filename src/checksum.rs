@@ -0,0 +1,93 @@
+use crate::{IdPool, Num};
+
+/// Bit used to store the parity of the id payload.
+const PARITY_BIT: Num = 1 << (Num::BITS - 1);
+
+/// Id pool handing out ids with an embedded parity bit.
+///
+/// The highest bit of each id is reserved for the parity of
+/// the remaining payload bits, which allows detecting ids
+/// that were corrupted on their way back to the pool. Create
+/// one with [`IdPool::new_with_checksum`].
+///
+/// [`IdPool::new_with_checksum`]: struct.IdPool.html#method.new_with_checksum
+#[derive(Debug, Clone)]
+pub struct ChecksumIdPool {
+    pool: IdPool,
+}
+
+impl IdPool {
+    /// Creates a new `ChecksumIdPool` with the given range of
+    /// payload values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is inverted or if it doesn't leave
+    /// the highest bit free for the parity.
+    pub fn new_with_checksum(range_for_payload: std::ops::Range<Num>) -> ChecksumIdPool {
+        assert!(
+            range_for_payload.end <= PARITY_BIT,
+            "payload range overlaps the parity bit"
+        );
+        ChecksumIdPool {
+            pool: IdPool::new_ranged(range_for_payload),
+        }
+    }
+}
+
+impl ChecksumIdPool {
+    /// Gets the current count of used ids.
+    pub fn used_count(&self) -> usize {
+        self.pool.used_count()
+    }
+
+    /// Returns a new id with the parity bit embedded or `None`
+    /// if there are no free ids in the pool.
+    pub fn request_id(&mut self) -> Option<Num> {
+        self.pool.request_id().map(encode)
+    }
+
+    /// Returns an id to the pool or `Err(Num)` if the id fails
+    /// validation or is already in the pool.
+    pub fn return_id(&mut self, id: Num) -> Result<(), Num> {
+        if !self.validate_id(id) {
+            return Err(id);
+        }
+        self.pool.return_id(id & !PARITY_BIT).map_err(|_| id)
+    }
+
+    /// Checks whether the parity bit of the id matches its
+    /// payload.
+    pub fn validate_id(&self, id: Num) -> bool {
+        id.count_ones() & 1 == 0
+    }
+}
+
+/// Sets the parity bit so that the total count of set bits
+/// is even.
+fn encode(payload: Num) -> Num {
+    if payload.count_ones() & 1 == 1 {
+        payload | PARITY_BIT
+    } else {
+        payload
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corrupted_id() {
+        let mut pool = IdPool::new_with_checksum(1..10);
+        let id = pool.request_id().unwrap();
+        assert!(pool.validate_id(id));
+        assert_eq!(1, id & !PARITY_BIT);
+        let corrupted = id ^ 0b100;
+        assert!(!pool.validate_id(corrupted));
+        assert_eq!(Err(corrupted), pool.return_id(corrupted));
+        assert_eq!(1, pool.used_count());
+        assert_eq!(Ok(()), pool.return_id(id));
+        assert_eq!(Some(id), pool.request_id());
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::{IdPool, Num};
+
+/// Id pool tagging every allocated id with the epoch it was
+/// allocated in, so that all ids from a past epoch can be freed
+/// at once.
+///
+/// This suits double-buffered systems, where everything from
+/// the previous frame or generation is released in bulk.
+///
+/// # Examples
+///
+/// ```
+/// # use id_pool::EpochIdPool;
+/// let mut pool = EpochIdPool::new();
+/// assert_eq!(Some(1), pool.request_id());
+/// let first = pool.current_epoch();
+/// pool.begin_epoch();
+/// assert_eq!(Some(2), pool.request_id());
+/// assert_eq!(vec![1], pool.free_epoch(first));
+/// assert_eq!(Some(1), pool.request_id());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EpochIdPool {
+    pool: IdPool,
+    /// Epoch new ids are allocated in
+    epoch: u64,
+    /// Allocated ids of each epoch
+    ids: BTreeMap<u64, BTreeSet<Num>>,
+    /// Epoch each allocated id was allocated in
+    epochs: HashMap<Num, u64>,
+}
+
+impl EpochIdPool {
+    /// Creates a new `EpochIdPool` with a default range, which
+    /// starts at `1` and ends at `Num::MAX`.
+    pub fn new() -> Self {
+        Self::new_ranged(1..Num::MAX)
+    }
+
+    /// Creates a new `EpochIdPool` with the given range.
+    pub fn new_ranged(range: std::ops::Range<Num>) -> Self {
+        Self {
+            pool: IdPool::new_ranged(range),
+            ..Self::default()
+        }
+    }
+
+    /// Gets the current count of used ids.
+    pub fn used_count(&self) -> usize {
+        self.pool.used_count()
+    }
+
+    /// Gets the epoch new ids are currently allocated in,
+    /// starting at `0`.
+    pub fn current_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Starts a new epoch, returning its number. Ids requested
+    /// from now on are tagged with the new epoch.
+    pub fn begin_epoch(&mut self) -> u64 {
+        self.epoch += 1;
+        self.epoch
+    }
+
+    /// Returns a new id, tagged with the current epoch, or `None`
+    /// if there are no free ids in the pool.
+    pub fn request_id(&mut self) -> Option<Num> {
+        let id = self.pool.request_id()?;
+        self.ids.entry(self.epoch).or_default().insert(id);
+        self.epochs.insert(id, self.epoch);
+        Some(id)
+    }
+
+    /// Returns an id to the pool or `Err(Num)` if the id is
+    /// already in the pool.
+    pub fn return_id(&mut self, id: Num) -> Result<(), Num> {
+        self.pool.return_id(id)?;
+        if let Some(epoch) = self.epochs.remove(&id) {
+            if let Some(ids) = self.ids.get_mut(&epoch) {
+                ids.remove(&id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns all ids allocated during the given epoch that are
+    /// still in use to the pool, in ascending order.
+    pub fn free_epoch(&mut self, epoch: u64) -> Vec<Num> {
+        let ids: Vec<Num> = self
+            .ids
+            .remove(&epoch)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        for &id in &ids {
+            self.epochs.remove(&id);
+            // ids are removed from the epoch once returned, so
+            // these are all in use
+            let _ = self.pool.return_id(id);
+        }
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_epoch() {
+        let mut pool = EpochIdPool::new_ranged(0..10);
+        let first = pool.current_epoch();
+        for _ in 0..3 {
+            pool.request_id();
+        }
+        assert_eq!(Ok(()), pool.return_id(1));
+        let second = pool.begin_epoch();
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Some(3), pool.request_id());
+        assert_eq!(vec![0, 2], pool.free_epoch(first));
+        assert_eq!(2, pool.used_count());
+        assert!(pool.free_epoch(first).is_empty());
+        assert_eq!(Some(0), pool.request_id());
+        assert_eq!(vec![0, 1, 3], pool.free_epoch(second));
+        assert_eq!(0, pool.used_count());
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::TryReserveError;
+use std::fmt;
+
+use crate::Num;
+
+/// Errors that can occur when manipulating an `IdPool`.
+///
+/// New variants may be added in future releases, so matches on
+/// this enum need a wildcard arm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IdPoolError {
+    /// Given range is not valid for the operation
+    InvalidRange,
+    /// Id in question lies outside of the configured range
+    OutOfRange(Num),
+    /// Id in question is currently allocated
+    AlreadyAllocated(Num),
+    /// Id in question is not currently allocated
+    NotAllocated(Num),
+    /// There are no free ids left in the pool
+    Exhausted {
+        /// Number of ids in the configured range
+        capacity: Num,
+        /// Number of ids in use at the time of the request
+        used: usize,
+    },
+    /// Pool state is internally inconsistent
+    Corrupt,
+    /// Textual representation of a pool could not be parsed
+    InvalidFormat,
+    /// Pools in question are configured over different ranges
+    RangeMismatch,
+    /// Memory for the pool state could not be allocated
+    OutOfMemory,
+}
+
+impl fmt::Display for IdPoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdPoolError::InvalidRange => write!(
+                f,
+                "invalid range, it must not be inverted and must fit the configured range"
+            ),
+            IdPoolError::OutOfRange(id) => write!(f, "id {} is out of range", id),
+            IdPoolError::AlreadyAllocated(id) => write!(f, "id {} is already allocated", id),
+            IdPoolError::NotAllocated(id) => {
+                write!(f, "id {} is not allocated, it's free or withheld", id)
+            }
+            IdPoolError::Exhausted { capacity, used } => {
+                write!(f, "no free ids left ({} of {} ids in use)", used, capacity)
+            }
+            IdPoolError::Corrupt => write!(f, "inconsistent pool state"),
+            IdPoolError::InvalidFormat => write!(f, "invalid pool format"),
+            IdPoolError::RangeMismatch => write!(f, "pools have different ranges"),
+            IdPoolError::OutOfMemory => write!(f, "out of memory for the pool state"),
+        }
+    }
+}
+
+impl std::error::Error for IdPoolError {}
+
+impl From<TryReserveError> for IdPoolError {
+    fn from(_: TryReserveError) -> Self {
+        IdPoolError::OutOfMemory
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::*;
+
+    #[test]
+    fn display() {
+        let cases = [
+            (
+                IdPoolError::InvalidRange,
+                "invalid range, it must not be inverted and must fit the configured range",
+            ),
+            (IdPoolError::OutOfRange(7), "id 7 is out of range"),
+            (
+                IdPoolError::AlreadyAllocated(7),
+                "id 7 is already allocated",
+            ),
+            (
+                IdPoolError::NotAllocated(7),
+                "id 7 is not allocated, it's free or withheld",
+            ),
+            (
+                IdPoolError::Exhausted {
+                    capacity: 10,
+                    used: 10,
+                },
+                "no free ids left (10 of 10 ids in use)",
+            ),
+            (IdPoolError::Corrupt, "inconsistent pool state"),
+            (IdPoolError::InvalidFormat, "invalid pool format"),
+            (IdPoolError::RangeMismatch, "pools have different ranges"),
+            (IdPoolError::OutOfMemory, "out of memory for the pool state"),
+        ];
+        for (error, message) in cases {
+            assert_eq!(message, error.to_string());
+        }
+    }
+
+    #[test]
+    fn conversions() {
+        fn reserve() -> Result<(), IdPoolError> {
+            Vec::<u64>::new().try_reserve(usize::MAX)?;
+            Ok(())
+        }
+        fn request() -> Result<Num, Box<dyn Error>> {
+            let mut pool = crate::IdPool::new_ranged(0..0);
+            Ok(pool.try_request_id()?)
+        }
+        assert_eq!(Err(IdPoolError::OutOfMemory), reserve());
+        let error = request().unwrap_err();
+        assert_eq!(
+            Some(&IdPoolError::Exhausted {
+                capacity: 0,
+                used: 0
+            }),
+            error.downcast_ref::<IdPoolError>()
+        );
+    }
+}
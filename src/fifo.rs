@@ -0,0 +1,97 @@
+use std::collections::VecDeque;
+
+use crate::{IdPool, Num};
+
+/// Id pool reusing returned ids in the order they were
+/// returned.
+///
+/// Instead of always handing out the lowest free id, returned
+/// ids are queued up and reissued first-in first-out, so that
+/// no returned id waits for reuse longer than the others. Once
+/// the queue is empty, new ids come from the untouched part
+/// of the range, lowest first.
+///
+/// # Examples
+///
+/// ```
+/// # use id_pool::FifoIdPool;
+/// let mut pool = FifoIdPool::new();
+/// assert_eq!(Some(1), pool.request_id());
+/// assert_eq!(Some(2), pool.request_id());
+/// assert_eq!(Ok(()), pool.return_id(2));
+/// assert_eq!(Ok(()), pool.return_id(1));
+/// assert_eq!(Some(2), pool.request_id());
+/// assert_eq!(Some(1), pool.request_id());
+/// assert_eq!(Some(3), pool.request_id());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FifoIdPool {
+    pool: IdPool,
+    /// Returned ids, in the order they were returned
+    queue: VecDeque<Num>,
+}
+
+impl FifoIdPool {
+    /// Creates a new `FifoIdPool` with a default range, which
+    /// starts at `1` and ends at `Num::MAX`.
+    pub fn new() -> Self {
+        Self::new_ranged(1..Num::MAX)
+    }
+
+    /// Creates a new `FifoIdPool` with the given range.
+    pub fn new_ranged(range: std::ops::Range<Num>) -> Self {
+        Self {
+            pool: IdPool::new_ranged(range),
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Gets the current count of used ids.
+    pub fn used_count(&self) -> usize {
+        self.pool.used_count()
+    }
+
+    /// Returns the earliest returned id, or a new id if there
+    /// are no returned ids waiting for reuse. Returns `None`
+    /// if there are no free ids in the pool.
+    pub fn request_id(&mut self) -> Option<Num> {
+        match self.queue.pop_front() {
+            Some(id) => {
+                // queued ids are kept free in the underlying pool
+                self.pool.allocate(id);
+                Some(id)
+            }
+            None => self.pool.request_id(),
+        }
+    }
+
+    /// Returns an id to the pool or `Err(Num)` if the id is
+    /// already in the pool.
+    pub fn return_id(&mut self, id: Num) -> Result<(), Num> {
+        self.pool.return_id(id)?;
+        self.queue.push_back(id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse_in_return_order() {
+        let mut pool = FifoIdPool::new_ranged(1..10);
+        for _ in 0..6 {
+            pool.request_id();
+        }
+        assert_eq!(Ok(()), pool.return_id(5));
+        assert_eq!(Ok(()), pool.return_id(3));
+        assert_eq!(Ok(()), pool.return_id(1));
+        assert_eq!(Err(3), pool.return_id(3));
+        assert_eq!(Some(5), pool.request_id());
+        assert_eq!(Some(3), pool.request_id());
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Some(7), pool.request_id());
+        assert_eq!(7, pool.used_count());
+    }
+}
@@ -0,0 +1,376 @@
+use crate::{Num, Range};
+
+/// Whether pools over a span fitting in `u32` store their free
+/// ranges as `u32` offsets, which only saves memory when `Num`
+/// is wider than that.
+const COMPACT: bool = cfg!(feature = "u64");
+
+/// List of the free ranges of an [`IdPool`], in descending
+/// order.
+///
+/// Ranges are handed out by value, since with compact storage
+/// they're kept as pairs of `u32` offsets from a base id, taking
+/// up half the memory of full `u64` bounds. Storing a range that
+/// doesn't fit the offsets, e.g. once the pool has grown past
+/// the span of `u32`, switches over to full bounds for good.
+///
+/// [`IdPool`]: struct.IdPool.html
+#[derive(Debug, Clone)]
+pub(crate) enum FreeList {
+    /// Ranges with full bounds
+    Wide(Vec<Range>),
+    /// Ranges as offsets from `base`
+    Compact { base: Num, ranges: Vec<[u32; 2]> },
+}
+
+impl FreeList {
+    /// Creates an empty list for a pool configured over `range`,
+    /// storing offsets if the span of the range fits in `u32`.
+    pub fn new(range: Range) -> Self {
+        Self::with_storage(range, COMPACT)
+    }
+
+    /// Same as [`new`], but picking the storage explicitly.
+    ///
+    /// [`new`]: #method.new
+    fn with_storage(range: Range, compact: bool) -> Self {
+        if compact && range.len() as u64 <= u32::MAX as u64 {
+            FreeList::Compact {
+                base: range.start,
+                ranges: Vec::new(),
+            }
+        } else {
+            FreeList::Wide(Vec::new())
+        }
+    }
+
+    /// Replaces the contents of the list, keeping its storage if
+    /// all of the ranges fit in it.
+    pub fn replace(&mut self, ranges: Vec<Range>) {
+        match self {
+            FreeList::Compact {
+                base,
+                ranges: compact,
+            } => {
+                let base = *base;
+                match ranges.iter().map(|&range| encode(base, range)).collect() {
+                    Some(encoded) => *compact = encoded,
+                    None => *self = FreeList::Wide(ranges),
+                }
+            }
+            FreeList::Wide(wide) => *wide = ranges,
+        }
+    }
+
+    /// Copies the ranges into a vector.
+    pub fn to_vec(&self) -> Vec<Range> {
+        match self {
+            FreeList::Wide(ranges) => ranges.clone(),
+            FreeList::Compact { .. } => self.iter().collect(),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        match self {
+            FreeList::Wide(ranges) => ranges.len(),
+            FreeList::Compact { ranges, .. } => ranges.len(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn capacity(&self) -> usize {
+        match self {
+            FreeList::Wide(ranges) => ranges.capacity(),
+            FreeList::Compact { ranges, .. } => ranges.capacity(),
+        }
+    }
+
+    #[cfg(test)]
+    /// Gets the heap memory taken up by the list, in bytes.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            FreeList::Wide(ranges) => ranges.capacity() * std::mem::size_of::<Range>(),
+            FreeList::Compact { ranges, .. } => ranges.capacity() * std::mem::size_of::<[u32; 2]>(),
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, i: usize) -> Option<Range> {
+        match self {
+            FreeList::Wide(ranges) => ranges.get(i).copied(),
+            FreeList::Compact { base, ranges } => ranges.get(i).map(|&r| decode(*base, r)),
+        }
+    }
+
+    /// Gets the highest range.
+    pub fn first(&self) -> Option<Range> {
+        self.get(0)
+    }
+
+    /// Gets the lowest range.
+    #[inline]
+    pub fn last(&self) -> Option<Range> {
+        self.len().checked_sub(1).and_then(|i| self.get(i))
+    }
+
+    #[inline]
+    pub fn iter(&self) -> Iter<'_> {
+        match self {
+            FreeList::Wide(ranges) => Iter::Wide(ranges.iter()),
+            FreeList::Compact { base, ranges } => Iter::Compact(*base, ranges.iter()),
+        }
+    }
+
+    /// Overwrites the range at index `i`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is out of bounds.
+    #[inline]
+    pub fn set(&mut self, i: usize, range: Range) {
+        match self {
+            FreeList::Wide(ranges) => ranges[i] = range,
+            FreeList::Compact { base, ranges } => match encode(*base, range) {
+                Some(encoded) => ranges[i] = encoded,
+                None => {
+                    self.widen();
+                    self.set(i, range);
+                }
+            },
+        }
+    }
+
+    /// Overwrites the lowest range.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the list is empty.
+    #[inline]
+    pub fn set_last(&mut self, range: Range) {
+        self.set(self.len() - 1, range);
+    }
+
+    pub fn push(&mut self, range: Range) {
+        let len = self.len();
+        self.insert(len, range);
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Option<Range> {
+        let last = self.last()?;
+        self.truncate(self.len() - 1);
+        Some(last)
+    }
+
+    #[inline]
+    pub fn insert(&mut self, i: usize, range: Range) {
+        match self {
+            FreeList::Wide(ranges) => ranges.insert(i, range),
+            FreeList::Compact { base, ranges } => match encode(*base, range) {
+                Some(encoded) => ranges.insert(i, encoded),
+                None => {
+                    self.widen();
+                    self.insert(i, range);
+                }
+            },
+        }
+    }
+
+    #[inline]
+    pub fn remove(&mut self, i: usize) -> Range {
+        match self {
+            FreeList::Wide(ranges) => ranges.remove(i),
+            FreeList::Compact { base, ranges } => decode(*base, ranges.remove(i)),
+        }
+    }
+
+    /// Removes the ranges above index `at`, returning them.
+    pub fn split_front(&mut self, at: usize) -> Vec<Range> {
+        let front = self.iter().take(at).collect();
+        match self {
+            FreeList::Wide(ranges) => drop(ranges.drain(..at)),
+            FreeList::Compact { ranges, .. } => drop(ranges.drain(..at)),
+        }
+        front
+    }
+
+    pub fn retain<F: FnMut(&Range) -> bool>(&mut self, mut f: F) {
+        match self {
+            FreeList::Wide(ranges) => ranges.retain(f),
+            FreeList::Compact { base, ranges } => {
+                let base = *base;
+                ranges.retain(|&r| f(&decode(base, r)))
+            }
+        }
+    }
+
+    #[inline]
+    pub fn partition_point<F: FnMut(&Range) -> bool>(&self, mut f: F) -> usize {
+        match self {
+            FreeList::Wide(ranges) => ranges.partition_point(f),
+            FreeList::Compact { base, ranges } => ranges.partition_point(|&r| f(&decode(*base, r))),
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        match self {
+            FreeList::Wide(ranges) => ranges.truncate(len),
+            FreeList::Compact { ranges, .. } => ranges.truncate(len),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        match self {
+            FreeList::Wide(ranges) => ranges.shrink_to(min_capacity),
+            FreeList::Compact { ranges, .. } => ranges.shrink_to(min_capacity),
+        }
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
+
+    /// Switches over to full bounds, keeping the capacity.
+    fn widen(&mut self) {
+        if let FreeList::Compact { ranges, .. } = self {
+            let mut wide = Vec::with_capacity(ranges.capacity());
+            wide.extend(self.iter());
+            *self = FreeList::Wide(wide);
+        }
+    }
+}
+
+impl From<Vec<Range>> for FreeList {
+    fn from(ranges: Vec<Range>) -> Self {
+        FreeList::Wide(ranges)
+    }
+}
+
+impl<'a> IntoIterator for &'a FreeList {
+    type Item = Range;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for FreeList {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FreeList {
+    /// Reads the ranges with full bounds, since the configured
+    /// range the offsets are taken from isn't known yet.
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<Range>::deserialize(deserializer).map(FreeList::from)
+    }
+}
+
+/// Iterator over the ranges of a [`FreeList`], highest first.
+///
+/// [`FreeList`]: enum.FreeList.html
+#[derive(Clone)]
+pub(crate) enum Iter<'a> {
+    Wide(std::slice::Iter<'a, Range>),
+    Compact(Num, std::slice::Iter<'a, [u32; 2]>),
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Range;
+
+    #[inline]
+    fn next(&mut self) -> Option<Range> {
+        match self {
+            Iter::Wide(iter) => iter.next().copied(),
+            Iter::Compact(base, iter) => iter.next().map(|&r| decode(*base, r)),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Iter::Wide(iter) => iter.size_hint(),
+            Iter::Compact(_, iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Range> {
+        match self {
+            Iter::Wide(iter) => iter.next_back().copied(),
+            Iter::Compact(base, iter) => iter.next_back().map(|&r| decode(*base, r)),
+        }
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+#[inline]
+fn encode(base: Num, range: Range) -> Option<[u32; 2]> {
+    if range.start < base || (range.end - base) as u64 > u32::MAX as u64 {
+        return None;
+    }
+    Some([(range.start - base) as u32, (range.end - base) as u32])
+}
+
+#[inline]
+fn decode(base: Num, [start, end]: [u32; 2]) -> Range {
+    Range {
+        start: base + start as Num,
+        end: base + end as Num,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: Num, end: Num) -> Range {
+        Range { start, end }
+    }
+
+    fn bounds(free: &FreeList) -> Vec<(Num, Num)> {
+        free.iter().map(|r| (r.start, r.end)).collect()
+    }
+
+    #[test]
+    fn compact_storage() {
+        let mut free = FreeList::with_storage(range(10, 1000), true);
+        free.push(range(500, 1000));
+        free.push(range(10, 20));
+        free.insert(1, range(100, 200));
+        assert!(matches!(free, FreeList::Compact { .. }));
+        assert_eq!(vec![(500, 1000), (100, 200), (10, 20)], bounds(&free));
+        assert_eq!(Some(10), free.last().map(|r| r.start));
+        assert_eq!(1, free.partition_point(|r| r.start > 200));
+        free.set(2, range(15, 20));
+        assert_eq!(Some(15), free.pop().map(|r| r.start));
+        assert_eq!(500, free.remove(0).start);
+        free.shrink_to_fit();
+        assert_eq!(8, free.heap_size());
+        // ranges below the base don't fit the offsets
+        free.push(range(1, 2));
+        assert!(matches!(free, FreeList::Wide(_)));
+        assert_eq!(vec![(100, 200), (1, 2)], bounds(&free));
+    }
+
+    #[test]
+    fn storage_choice() {
+        let free = FreeList::with_storage(range(0, Num::MAX), true);
+        let fits = Num::MAX as u64 <= u32::MAX as u64;
+        assert_eq!(fits, matches!(free, FreeList::Compact { .. }));
+        let free = FreeList::with_storage(range(0, 10), false);
+        assert!(matches!(free, FreeList::Wide(_)));
+    }
+}
@@ -0,0 +1,53 @@
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{IdPool, Num};
+
+/// Maximum width of the range of an arbitrary pool.
+const MAX_WIDTH: u16 = 1024;
+
+/// Produces pools with a bounded range, brought into an
+/// arbitrary state by a sequence of valid operations.
+impl<'a> Arbitrary<'a> for IdPool {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let start = u.int_in_range(0..=MAX_WIDTH)? as Num;
+        let width = u.int_in_range(0..=MAX_WIDTH)? as Num;
+        let mut pool = IdPool::new_ranged(start..start + width);
+        while !u.is_empty() {
+            match u.int_in_range(0..=3u8)? {
+                0 | 1 => {
+                    pool.request_id();
+                }
+                2 if width > 0 => {
+                    let id = start + u.int_in_range(0..=width - 1)?;
+                    let _ = pool.return_id(id);
+                }
+                _ if width > 0 => {
+                    let id = start + u.int_in_range(0..=width - 1)?;
+                    let _ = pool.block_id(id);
+                }
+                _ => (),
+            }
+        }
+        Ok(pool)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arbitrary_pools_are_consistent() {
+        let mut seed: u32 = 7;
+        for len in 0..64 {
+            let bytes: Vec<u8> = (0..len * 16)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    (seed >> 16) as u8
+                })
+                .collect();
+            let pool = IdPool::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+            pool.assert_consistent();
+        }
+    }
+}
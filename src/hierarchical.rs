@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+
+use crate::{IdPool, IdPoolError, Num};
+
+/// Id pool splitting its ids into fixed-size zones, handed out
+/// by a parent pool to child pools on demand.
+///
+/// Combined ids are laid out zone by zone, so an id belongs to
+/// zone `id / zone_size`. A zone is only taken from the parent
+/// once all zones in use are full, and released back to it as
+/// soon as all of its ids are returned.
+///
+/// # Examples
+///
+/// ```
+/// # use id_pool::HierarchicalIdPool;
+/// let mut pool = HierarchicalIdPool::new(4, 2);
+/// assert_eq!(Some(0), pool.request_id());
+/// assert_eq!(Some(1), pool.request_id());
+/// assert_eq!(Some(2), pool.request_id());
+/// assert_eq!(2, pool.zones_in_use());
+/// assert_eq!(Ok(()), pool.return_id(2));
+/// assert_eq!(1, pool.zones_in_use());
+/// ```
+#[derive(Debug, Clone)]
+pub struct HierarchicalIdPool {
+    /// Pool of zone numbers
+    parent: IdPool,
+    /// Number of ids in each zone
+    zone_size: Num,
+    /// Pools of the zones taken from the parent, by zone number
+    children: BTreeMap<Num, IdPool>,
+}
+
+impl HierarchicalIdPool {
+    /// Creates a new `HierarchicalIdPool` with `zones` zones of
+    /// `zone_size` ids each, spanning ids from `0` up to
+    /// `zones * zone_size`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `zone_size` is zero or the ids don't fit into
+    /// `Num`.
+    pub fn new(zones: Num, zone_size: Num) -> Self {
+        assert!(zone_size != 0, "zone size must be non-zero");
+        assert!(
+            zones.checked_mul(zone_size).is_some(),
+            "{}",
+            IdPoolError::InvalidRange
+        );
+        Self {
+            parent: IdPool::new_ranged(0..zones),
+            zone_size,
+            children: BTreeMap::new(),
+        }
+    }
+
+    /// Gets the number of ids in each zone.
+    pub fn zone_size(&self) -> Num {
+        self.zone_size
+    }
+
+    /// Gets the number of zones currently taken from the parent.
+    pub fn zones_in_use(&self) -> usize {
+        self.children.len()
+    }
+
+    /// Gets the current count of used ids.
+    pub fn used_count(&self) -> usize {
+        self.children.values().map(IdPool::used_count).sum()
+    }
+
+    /// Returns a new id or `None` if there are no free ids left
+    /// in any of the zones.
+    pub fn request_id(&mut self) -> Option<Num> {
+        if let Some(id) = self.children.values_mut().find_map(IdPool::request_id) {
+            return Some(id);
+        }
+        let zone = self.parent.request_id()?;
+        let start = zone * self.zone_size;
+        let mut child = IdPool::new_ranged(start..start + self.zone_size);
+        let id = child.request_id();
+        self.children.insert(zone, child);
+        id
+    }
+
+    /// Returns an id to the zone it belongs to, releasing the
+    /// zone back to the parent once all of its ids are free.
+    /// Returns `Err(IdPoolError::OutOfRange)` if the id lies
+    /// outside of all zones, or `Err(IdPoolError::NotAllocated)`
+    /// if the id is not currently allocated.
+    pub fn return_id(&mut self, id: Num) -> Result<(), IdPoolError> {
+        let zone = id / self.zone_size;
+        if zone >= self.parent.range.end() {
+            return Err(IdPoolError::OutOfRange(id));
+        }
+        let child = self
+            .children
+            .get_mut(&zone)
+            .ok_or(IdPoolError::NotAllocated(id))?;
+        child.return_id(id).map_err(IdPoolError::NotAllocated)?;
+        if child.used_count() == 0 {
+            self.children.remove(&zone);
+            // the zone was taken from the parent along with the child
+            self.parent.return_id(zone).unwrap();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zones() {
+        let mut pool = HierarchicalIdPool::new(3, 4);
+        let ids: Vec<Num> = (0..10).map(|_| pool.request_id().unwrap()).collect();
+        assert_eq!((0..10).collect::<Vec<_>>(), ids);
+        assert_eq!(3, pool.zones_in_use());
+        assert_eq!(Some(10), pool.request_id());
+        assert_eq!(Some(11), pool.request_id());
+        assert_eq!(None, pool.request_id());
+        assert_eq!(Err(IdPoolError::OutOfRange(12)), pool.return_id(12));
+        // freeing the whole middle zone hands it back to the parent
+        for id in 4..8 {
+            assert_eq!(Ok(()), pool.return_id(id));
+        }
+        assert_eq!(Err(IdPoolError::NotAllocated(5)), pool.return_id(5));
+        assert_eq!(2, pool.zones_in_use());
+        assert!(pool.parent.is_free(1));
+        assert_eq!(Ok(()), pool.return_id(0));
+        assert_eq!(Err(IdPoolError::NotAllocated(0)), pool.return_id(0));
+        // free ids of zones in use come before reclaimed zones
+        assert_eq!(Some(0), pool.request_id());
+        assert_eq!(Some(4), pool.request_id());
+        assert_eq!(3, pool.zones_in_use());
+        assert_eq!(9, pool.used_count());
+    }
+}
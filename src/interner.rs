@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::{IdPool, Num};
+
+/// Maps arbitrary keys to small ids, handing out the same id
+/// for the same key until the key is removed again.
+///
+/// Ids of removed keys go back to the underlying pool and are
+/// reused for new keys, lowest first.
+///
+/// # Examples
+///
+/// ```
+/// # use id_pool::Interner;
+/// let mut interner = Interner::new();
+/// assert_eq!(1, interner.intern("foo"));
+/// assert_eq!(2, interner.intern("bar"));
+/// assert_eq!(1, interner.intern("foo"));
+/// assert_eq!(Some(&"bar"), interner.resolve(2));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Interner<K> {
+    pool: IdPool,
+    /// Id of each interned key
+    ids: HashMap<K, Num>,
+    /// Key each allocated id was handed out for
+    keys: HashMap<Num, K>,
+}
+
+impl<K: Hash + Eq + Clone> Default for Interner<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone> Interner<K> {
+    /// Creates a new `Interner` with a default range, which
+    /// starts at `1` and ends at `Num::MAX`.
+    pub fn new() -> Self {
+        Self::new_ranged(1..Num::MAX)
+    }
+
+    /// Creates a new `Interner` with the given range.
+    pub fn new_ranged(range: std::ops::Range<Num>) -> Self {
+        Self {
+            pool: IdPool::new_ranged(range),
+            ids: HashMap::new(),
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Gets the number of interned keys.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Checks whether no keys are interned.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Returns the id of the given key, handing out a new id
+    /// if the key isn't interned yet.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is new and there are no free ids left.
+    pub fn intern(&mut self, key: K) -> Num {
+        if let Some(&id) = self.ids.get(&key) {
+            return id;
+        }
+        let id = match self.pool.try_request_id() {
+            Ok(id) => id,
+            Err(e) => panic!("{}", e),
+        };
+        self.keys.insert(id, key.clone());
+        self.ids.insert(key, id);
+        id
+    }
+
+    /// Gets the id of the given key without interning it.
+    pub fn get(&self, key: &K) -> Option<Num> {
+        self.ids.get(key).copied()
+    }
+
+    /// Gets the key the given id was handed out for.
+    pub fn resolve(&self, id: Num) -> Option<&K> {
+        self.keys.get(&id)
+    }
+
+    /// Removes the given key, returning its id to the pool.
+    /// Returns the id the key had or `None` if it wasn't
+    /// interned.
+    pub fn unintern(&mut self, key: &K) -> Option<Num> {
+        let id = self.ids.remove(key)?;
+        self.keys.remove(&id);
+        // every interned key holds an id allocated from the pool
+        self.pool.return_id(id).unwrap();
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intern() {
+        let mut interner = Interner::new_ranged(0..2);
+        let foo = interner.intern("foo".to_string());
+        assert_eq!(foo, interner.intern("foo".to_string()));
+        let bar = interner.intern("bar".to_string());
+        assert_ne!(foo, bar);
+        assert_eq!(2, interner.len());
+        assert_eq!(Some(&"bar".to_string()), interner.resolve(bar));
+        assert_eq!(Some(foo), interner.unintern(&"foo".to_string()));
+        assert_eq!(None, interner.unintern(&"foo".to_string()));
+        assert_eq!(None, interner.resolve(foo));
+        assert_eq!(None, interner.get(&"foo".to_string()));
+        // the freed id is handed out for the next new key
+        assert_eq!(foo, interner.intern("baz".to_string()));
+        assert_eq!(Some(&"baz".to_string()), interner.resolve(foo));
+    }
+}
@@ -11,8 +11,81 @@
 //! the course of the program, ids can be returned to the
 //! pool to be reused for subsequent id request calls.
 //!
+//! With `u64` ids, pools configured over a span that fits in
+//! `u32` store their free ranges as `u32` offsets internally,
+//! halving the memory taken up by each range.
+//!
 //! [`IdPool`]: struct.IdPool.html
 
+// conversions between `Num` and `usize` are only no-ops with
+// the default feature
+#![allow(clippy::unnecessary_cast)]
+
+#[cfg(feature = "async")]
+mod async_pool;
+mod backend;
+#[cfg(feature = "bloom")]
+mod bloom;
+mod btree;
+#[cfg(feature = "buffered")]
+mod buffered;
+mod checksum;
+mod epoch;
+mod error;
+mod fifo;
+mod free_list;
+#[cfg(feature = "arbitrary")]
+mod fuzz;
+mod hierarchical;
+mod interner;
+mod map;
+mod ordered;
+#[cfg(feature = "queue")]
+mod queue;
+#[cfg(feature = "rand_core")]
+mod random;
+#[cfg(feature = "serde")]
+pub mod serde_flat;
+mod shuffled;
+mod slab;
+mod sync;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod wal;
+
+#[cfg(feature = "async")]
+pub use async_pool::AsyncIdPool;
+pub use backend::PoolBackend;
+#[cfg(feature = "bloom")]
+pub use bloom::BloomFilter;
+pub use btree::BTreeIdPool;
+#[cfg(feature = "buffered")]
+pub use buffered::BufferedIdSource;
+pub use checksum::ChecksumIdPool;
+pub use epoch::EpochIdPool;
+pub use error::IdPoolError;
+pub use fifo::FifoIdPool;
+pub use hierarchical::HierarchicalIdPool;
+pub use interner::Interner;
+pub use map::IdMap;
+pub use ordered::OrderedIdPool;
+#[cfg(feature = "queue")]
+pub use queue::QueuedIdSource;
+#[cfg(feature = "rand_core")]
+pub use random::RandomIdPool;
+pub use shuffled::ShuffledIdPool;
+pub use slab::IdSlab;
+pub use sync::{SyncIdGuard, SyncIdPool};
+pub use wal::LoggedIdPool;
+
+use free_list::FreeList;
+
+#[cfg(debug_assertions)]
+use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
@@ -25,6 +98,15 @@ type Num = u64;
 #[cfg(feature = "usize")]
 type Num = usize;
 
+#[cfg(feature = "u16")]
+type NonZeroNum = std::num::NonZeroU16;
+#[cfg(feature = "u32")]
+type NonZeroNum = std::num::NonZeroU32;
+#[cfg(feature = "u64")]
+type NonZeroNum = std::num::NonZeroU64;
+#[cfg(feature = "usize")]
+type NonZeroNum = std::num::NonZeroUsize;
+
 /// Custom range struct
 #[derive(Copy, Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -38,6 +120,21 @@ impl Range {
         self.end - self.start
     }
 
+    /// Checks whether the range is empty.
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    /// Gets the first value of the range.
+    pub fn start(&self) -> Num {
+        self.start
+    }
+
+    /// Gets the value right after the last value of the range.
+    pub fn end(&self) -> Num {
+        self.end
+    }
+
     /// Calculates whether a given value is contained
     /// within the range.
     pub fn contains(&self, value: &Num) -> bool {
@@ -45,6 +142,179 @@ impl Range {
     }
 }
 
+impl From<std::ops::Range<Num>> for Range {
+    fn from(range: std::ops::Range<Num>) -> Self {
+        Range {
+            start: range.start,
+            end: range.end,
+        }
+    }
+}
+
+impl From<Range> for std::ops::Range<Num> {
+    fn from(range: Range) -> Self {
+        range.start..range.end
+    }
+}
+
+/// Summary of a batch of ids returned with
+/// [`IdPool::bulk_return`].
+///
+/// [`IdPool::bulk_return`]: struct.IdPool.html#method.bulk_return
+#[derive(Copy, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BulkReturnReport {
+    /// Number of ids returned to the pool
+    pub returned: usize,
+    /// Number of ids that were already free
+    pub already_free: usize,
+    /// Number of ids outside of the configured range
+    pub out_of_range: usize,
+    /// Number of blocked or reserved ids, which can't be returned
+    pub withheld: usize,
+    /// Number of times a returned id was merged with an adjacent
+    /// free range
+    pub merges: usize,
+}
+
+/// Adjustments made by [`IdPool::reconcile`] to match an
+/// authoritative set of used ids.
+///
+/// [`IdPool::reconcile`]: struct.IdPool.html#method.reconcile
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReconcileReport {
+    /// Ranges of ids that were used but are now free, in
+    /// ascending order
+    pub freed: Vec<std::ops::Range<Num>>,
+    /// Ranges of ids that were free but are now used, in
+    /// ascending order
+    pub reserved: Vec<std::ops::Range<Num>>,
+    /// Number of authoritative ids that can't be used, lying
+    /// outside of the configured range or being blocked or
+    /// reserved
+    pub ignored: usize,
+}
+
+/// Token identifying the ids handed out by
+/// [`IdPool::request_ids_with_token`], which can be passed to
+/// [`IdPool::rollback_allocation`] to return all of them at once.
+///
+/// [`IdPool::request_ids_with_token`]: struct.IdPool.html#method.request_ids_with_token
+/// [`IdPool::rollback_allocation`]: struct.IdPool.html#method.rollback_allocation
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[must_use]
+pub struct AllocationToken {
+    /// Runs of allocated ids, in ascending order
+    ranges: Vec<std::ops::Range<Num>>,
+}
+
+impl AllocationToken {
+    /// Gets the runs of ids covered by the token, in ascending
+    /// order.
+    pub fn ranges(&self) -> &[std::ops::Range<Num>] {
+        &self.ranges
+    }
+}
+
+/// Id handed out by [`IdPool::request_allocated`].
+///
+/// Dereferences to the raw `Num`, so it can be used in place of
+/// a plain id, while only ever being created by a pool. This
+/// eases a gradual migration towards typed ids.
+///
+/// [`IdPool::request_allocated`]: struct.IdPool.html#method.request_allocated
+#[derive(Copy, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AllocatedId {
+    id: Num,
+}
+
+impl AllocatedId {
+    /// Unwraps the raw id.
+    pub fn into_raw(self) -> Num {
+        self.id
+    }
+}
+
+impl std::ops::Deref for AllocatedId {
+    type Target = Num;
+
+    fn deref(&self) -> &Num {
+        &self.id
+    }
+}
+
+/// Policy for growing the configured range of a pool once it's
+/// exhausted, see [`IdPool::request_id_or_grow`].
+///
+/// [`IdPool::request_id_or_grow`]: struct.IdPool.html#method.request_id_or_grow
+#[derive(Copy, Debug, Clone)]
+pub enum GrowthPolicy {
+    /// Grow by the given number of ids
+    Fixed(Num),
+    /// Double the capacity, so that repeated exhaustion grows
+    /// the pool geometrically
+    Double,
+    /// Grow by the number of ids computed from the current
+    /// capacity
+    Custom(fn(Num) -> Num),
+}
+
+impl GrowthPolicy {
+    /// Gets the number of ids to grow a pool of the given
+    /// capacity by, which is never zero.
+    fn growth(&self, capacity: Num) -> Num {
+        let growth = match *self {
+            GrowthPolicy::Fixed(n) => n,
+            GrowthPolicy::Double => capacity,
+            GrowthPolicy::Custom(f) => f(capacity),
+        };
+        growth.max(1)
+    }
+}
+
+/// Operation on a pool, as replayed by [`IdPool::simulate`].
+///
+/// [`IdPool::simulate`]: struct.IdPool.html#method.simulate
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// Request a new id
+    Request,
+    /// Return the given id
+    Return(Num),
+}
+
+/// Snapshot of the occupancy and fragmentation of a pool.
+#[derive(Copy, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Number of ids in use
+    pub used: usize,
+    /// Number of free ids
+    pub available: Num,
+    /// Number of free ranges
+    pub free_ranges: usize,
+    /// Length of the longest free range
+    pub largest_free_range: Num,
+}
+
+/// Low watermark on the count of free ids, along with the
+/// callback to invoke once it's crossed.
+#[derive(Clone)]
+struct Watermark {
+    threshold: Num,
+    /// Shared between clones of the pool
+    callback: Arc<Mutex<dyn FnMut() + Send>>,
+    /// Whether the callback fires on the next crossing
+    armed: bool,
+}
+
+impl fmt::Debug for Watermark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watermark")
+            .field("threshold", &self.threshold)
+            .field("armed", &self.armed)
+            .finish()
+    }
+}
+
 /// Keeps track of free ids within a specified range,
 /// handles requests and returns of ids based on internal
 /// state.
@@ -73,12 +343,127 @@ impl Range {
 /// assert_eq!(Some(4), pool.request_id());
 /// ```
 #[derive(Debug, Clone)]
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(remote = "Self")
+)]
 pub struct IdPool {
+    /// Configured range of ids, missing from payloads written
+    /// before it was stored
+    #[cfg_attr(feature = "serde", serde(default = "Range::missing"))]
+    range: Range,
     /// List of available id ranges
-    free: Vec<Range>,
+    free: FreeList,
     /// Number of ids currently in use
     used: usize,
+    /// Ids permanently withheld from allocation
+    #[cfg_attr(feature = "serde", serde(default))]
+    blocked: BTreeSet<Num>,
+    /// Spans of ids reserved at construction, in ascending order
+    #[cfg_attr(feature = "serde", serde(default))]
+    reserved: Vec<Range>,
+    /// Number of ids handed out over the pool's lifetime
+    #[cfg_attr(feature = "serde", serde(default))]
+    total_allocations: u64,
+    /// Number of requests that found the pool exhausted
+    #[cfg_attr(feature = "serde", serde(default))]
+    exhaustion_count: u64,
+    /// Whether returned ids are kept as separate ranges
+    #[cfg_attr(feature = "serde", serde(default))]
+    lazy_coalescing: bool,
+    /// Count of free ranges above which returns trigger compaction
+    #[cfg_attr(feature = "serde", serde(default))]
+    coalesce_threshold: Option<usize>,
+    /// Ids at or above which `request_id` won't hand out
+    #[cfg_attr(feature = "serde", serde(default))]
+    ceiling: Option<Num>,
+    /// Number of free ids set aside for a later batch
+    #[cfg_attr(feature = "serde", serde(default))]
+    earmarked: Num,
+    /// Policy for growing the range once exhausted
+    #[cfg_attr(feature = "serde", serde(skip))]
+    growth: Option<GrowthPolicy>,
+    /// Number of operations that allocated or freed ids
+    #[cfg_attr(feature = "serde", serde(default))]
+    version: u64,
+    /// Used counts at the most recent versions
+    #[cfg_attr(feature = "serde", serde(default))]
+    history: VecDeque<(u64, usize)>,
+    /// Low watermark on the count of free ids
+    #[cfg_attr(feature = "serde", serde(skip))]
+    watermark: Option<Watermark>,
+    /// Latencies of `request_id` and `return_id` calls, in
+    /// nanoseconds
+    #[cfg(feature = "hdrhistogram")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    latency: Option<hdrhistogram::Histogram<u64>>,
+    /// Percentage of the capacity in use above which a warning
+    /// is logged, and whether the warning is armed
+    #[cfg(feature = "log")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    capacity_warning: Option<(u8, bool)>,
+    /// Ids handed out by `request_id`, guarding against
+    /// duplicate hand-outs in debug builds
+    #[cfg(debug_assertions)]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    issued: HashSet<Num>,
+}
+
+#[cfg(feature = "serde")]
+impl Range {
+    /// Placeholder for a range absent from the payload, inverted
+    /// so no pool can hold it.
+    fn missing() -> Self {
+        Range {
+            start: Num::MAX,
+            end: 0,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for IdPool {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        IdPool::serialize(self, serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for IdPool {
+    /// Payloads written before the configured range was stored
+    /// only hold `free` and `used`. Since ids are handed out from
+    /// the bottom, the highest free range still ends at the
+    /// configured end, and the range is taken to span the free
+    /// and used ids below it. A fully used pool is taken to end
+    /// at `Num::MAX`, like the default range.
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut pool = IdPool::deserialize(deserializer)?;
+        if pool.range.start > pool.range.end {
+            let end = pool.free.iter().map(|r| r.end).max().unwrap_or(Num::MAX);
+            let span = pool.available() as u128 + pool.used as u128;
+            if span > end as u128 {
+                return Err(serde::de::Error::custom(
+                    "used count exceeds the ids below the free ranges",
+                ));
+            }
+            pool.range = Range {
+                start: end - span as Num,
+                end,
+            };
+        }
+        // offsets are taken from the configured range
+        let ranges = pool.free.to_vec();
+        pool.free = FreeList::new(pool.range);
+        pool.free.replace(ranges);
+        Ok(pool)
+    }
+}
+
+impl Default for IdPool {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl IdPool {
@@ -88,146 +473,3986 @@ impl IdPool {
         Self::new_ranged(1..Num::MAX)
     }
 
+    /// Creates a new `IdPool` with a range starting at `0` and
+    /// ending at `Num::MAX`. Unlike with [`new`], `0` is a valid
+    /// id here and is the first one handed out.
+    ///
+    /// [`new`]: #method.new
+    pub fn new_including_zero() -> Self {
+        Self::new_ranged(0..Num::MAX)
+    }
+
     /// Creates a new `IdPool` with the given range.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range start is greater than the range end.
+    /// See [`try_new_ranged`] for a non-panicking version.
+    ///
+    /// [`try_new_ranged`]: #method.try_new_ranged
     pub fn new_ranged(range: std::ops::Range<Num>) -> Self {
-        let vec = vec![Range {
+        match Self::try_new_ranged(range) {
+            Ok(pool) => pool,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Creates a new `IdPool` with the given range or returns
+    /// `Err(IdPoolError::InvalidRange)` if the range start is
+    /// greater than the range end.
+    pub fn try_new_ranged(range: std::ops::Range<Num>) -> Result<Self, IdPoolError> {
+        if range.start > range.end {
+            return Err(IdPoolError::InvalidRange);
+        }
+        let range = Range {
             start: range.start,
             end: range.end,
-        }];
-        Self { free: vec, used: 0 }
+        };
+        // an empty range leaves no free ids to begin with
+        let mut free = FreeList::new(range);
+        if !range.is_empty() {
+            free.push(range);
+        }
+        Ok(Self {
+            range,
+            free,
+            used: 0,
+            blocked: BTreeSet::new(),
+            reserved: Vec::new(),
+            total_allocations: 0,
+            exhaustion_count: 0,
+            lazy_coalescing: false,
+            coalesce_threshold: None,
+            ceiling: None,
+            earmarked: 0,
+            growth: None,
+            version: 0,
+            history: VecDeque::new(),
+            watermark: None,
+            #[cfg(feature = "hdrhistogram")]
+            latency: None,
+            #[cfg(feature = "log")]
+            capacity_warning: None,
+            #[cfg(debug_assertions)]
+            issued: HashSet::new(),
+        })
+    }
+
+    /// Creates a new `IdPool` with the given range, excluding
+    /// all of the `reserved` spans from allocation.
+    ///
+    /// Reserved ids are never handed out and can't be returned
+    /// or unblocked, they stay withheld for the lifetime of the
+    /// pool. Returns `Err(IdPoolError::InvalidRange)` if the
+    /// configured range or any of the reserved ones is inverted,
+    /// or if the reserved ranges overlap or don't fit in the
+    /// configured range.
+    pub fn with_reserved_ranges(
+        configured: std::ops::Range<Num>,
+        reserved: &[std::ops::Range<Num>],
+    ) -> Result<Self, IdPoolError> {
+        let mut pool = Self::try_new_ranged(configured)?;
+        let mut ranges: Vec<Range> = reserved
+            .iter()
+            .filter(|range| range.start != range.end)
+            .map(|range| Range::from(range.clone()))
+            .collect();
+        ranges.sort_by_key(|range| range.start);
+        let mut min = pool.range.start;
+        for range in &ranges {
+            if range.start < min || range.end < range.start || range.end > pool.range.end {
+                return Err(IdPoolError::InvalidRange);
+            }
+            min = range.end;
+        }
+        pool.reserved = ranges;
+        pool.free_from(pool.range.start);
+        pool.debug_assert_consistent();
+        Ok(pool)
+    }
+
+    /// Creates a new `IdPool` handing out only ids that are valid
+    /// Unicode scalar values, so that every id can be converted
+    /// to a `char`.
+    ///
+    /// The range spans from `0` up to `0x110000`, with the
+    /// surrogate code points `0xD800..0xE000` reserved. Not
+    /// available with the `u16` feature, which can't represent
+    /// the whole range.
+    #[cfg(not(feature = "u16"))]
+    pub fn new_unicode_scalar() -> Self {
+        let surrogates = 0xD800..0xE000;
+        // the reserved range lies within the configured one
+        Self::with_reserved_ranges(0..0x11_0000, std::slice::from_ref(&surrogates)).unwrap()
+    }
+
+    /// Creates a new `IdPool` handing out only the given ids,
+    /// coalesced into ranges internally.
+    ///
+    /// The configured range spans from the lowest to the highest
+    /// of the ids, with the ids in between that are not on the
+    /// list reserved, as with [`with_reserved_ranges`]. This way
+    /// only ids from the list can ever be returned. Duplicates
+    /// are ignored. Returns `Err(IdPoolError::OutOfRange)` if
+    /// the list contains `Num::MAX`, which can't be included in
+    /// a configured range.
+    ///
+    /// [`with_reserved_ranges`]: #method.with_reserved_ranges
+    pub fn from_free_list(free: &[Num]) -> Result<Self, IdPoolError> {
+        let mut ids = free.to_vec();
+        ids.sort_unstable();
+        ids.dedup();
+        let (first, last) = match (ids.first(), ids.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return Self::try_new_ranged(0..0),
+        };
+        let end = last.checked_add(1).ok_or(IdPoolError::OutOfRange(last))?;
+        let reserved: Vec<std::ops::Range<Num>> = ids
+            .windows(2)
+            .filter(|pair| pair[1] > pair[0] + 1)
+            .map(|pair| pair[0] + 1..pair[1])
+            .collect();
+        Self::with_reserved_ranges(first..end, &reserved)
+    }
+
+    /// Creates a new `IdPool` handing out only ids from the given
+    /// ranges, which together make up the allocatable universe.
+    ///
+    /// The configured range spans from the start of the first
+    /// range to the end of the last one, with the gaps between
+    /// them reserved, as with [`with_reserved_ranges`]. Ids in the
+    /// gaps are treated as out of range. Returns
+    /// `Err(IdPoolError::InvalidRange)` unless the ranges are
+    /// non-empty, sorted in ascending order and disjoint.
+    ///
+    /// [`with_reserved_ranges`]: #method.with_reserved_ranges
+    pub fn new_from_ranges(allowed: &[std::ops::Range<Num>]) -> Result<Self, IdPoolError> {
+        if allowed.iter().any(|range| range.start >= range.end)
+            || allowed.windows(2).any(|pair| pair[0].end > pair[1].start)
+        {
+            return Err(IdPoolError::InvalidRange);
+        }
+        let (first, last) = match (allowed.first(), allowed.last()) {
+            (Some(first), Some(last)) => (first.start, last.end),
+            _ => return Self::try_new_ranged(0..0),
+        };
+        let gaps: Vec<std::ops::Range<Num>> = allowed
+            .windows(2)
+            .filter(|pair| pair[0].end < pair[1].start)
+            .map(|pair| pair[0].end..pair[1].start)
+            .collect();
+        Self::with_reserved_ranges(first..last, &gaps)
+    }
+
+    /// Enables or disables lazy coalescing of returned ids.
+    ///
+    /// With lazy coalescing enabled, each returned id is kept
+    /// as its own free range instead of being merged with the
+    /// adjacent ones, until [`compact_ranges_in_place`] is
+    /// called. This makes it easy to see exactly which ids
+    /// were returned, at the cost of memory and lookup time
+    /// growing with every returned id rather than with the
+    /// actual fragmentation of the free space.
+    ///
+    /// [`compact_ranges_in_place`]: #method.compact_ranges_in_place
+    pub fn with_lazy_coalescing(mut self, enabled: bool) -> Self {
+        self.lazy_coalescing = enabled;
+        self
+    }
+
+    /// Sets the policy used by [`request_id_or_grow`] to grow the
+    /// configured range once the pool is exhausted.
+    ///
+    /// The policy is not serialized, it has to be set again on
+    /// a deserialized pool.
+    ///
+    /// [`request_id_or_grow`]: #method.request_id_or_grow
+    pub fn with_growth_policy(mut self, policy: GrowthPolicy) -> Self {
+        self.growth = Some(policy);
+        self
+    }
+
+    /// Logs a warning through the `log` crate once the used ids
+    /// reach `percent` percent of the capacity, not counting
+    /// reserved ids. The warning is logged once per crossing of
+    /// the threshold, and re-armed once usage drops below it.
+    #[cfg(feature = "log")]
+    pub fn with_capacity_warning_threshold(mut self, percent: u8) -> Self {
+        self.capacity_warning = Some((percent, true));
+        self
+    }
+
+    /// Enables recording the latency of every [`request_id`] and
+    /// [`return_id`] call in a histogram, which can be inspected
+    /// with [`latency_snapshot`]. Rising return latencies are a
+    /// sign of growing fragmentation.
+    ///
+    /// The histogram is not serialized.
+    ///
+    /// [`request_id`]: #method.request_id
+    /// [`return_id`]: #method.return_id
+    /// [`latency_snapshot`]: #method.latency_snapshot
+    #[cfg(feature = "hdrhistogram")]
+    pub fn with_latency_histogram(mut self) -> Self {
+        // three significant figures, growing as needed
+        self.latency = Some(hdrhistogram::Histogram::new(3).unwrap());
+        self
+    }
+
+    /// Returns a copy of the latency histogram, in nanoseconds,
+    /// or `None` if latency recording isn't enabled with
+    /// [`with_latency_histogram`].
+    ///
+    /// [`with_latency_histogram`]: #method.with_latency_histogram
+    #[cfg(feature = "hdrhistogram")]
+    pub fn latency_snapshot(&self) -> Option<hdrhistogram::Histogram<u64>> {
+        self.latency.clone()
+    }
+
+    /// Sets the count of free ranges above which returning an id
+    /// compacts the free ranges with [`compact_ranges_in_place`].
+    ///
+    /// Adjacent free ranges are merged on return anyway, so this
+    /// only makes a difference with lazy coalescing enabled, where
+    /// it bounds the number of free ranges automatically.
+    ///
+    /// [`compact_ranges_in_place`]: #method.compact_ranges_in_place
+    pub fn with_coalesce_threshold(mut self, threshold: usize) -> Self {
+        self.coalesce_threshold = Some(threshold);
+        self
+    }
+
+    /// Creates a new `IdPool` with the given range, suitable for
+    /// use with [`request_nonzero`]. Returns
+    /// `Err(IdPoolError::InvalidRange)` if the range includes `0`
+    /// or is inverted.
+    ///
+    /// [`request_nonzero`]: #method.request_nonzero
+    pub fn try_new_nonzero(range: std::ops::Range<Num>) -> Result<Self, IdPoolError> {
+        if range.start == 0 {
+            return Err(IdPoolError::InvalidRange);
+        }
+        Self::try_new_ranged(range)
     }
 
     /// Gets the current count of used ids.
+    ///
+    /// This is a constant-time operation, the count is kept
+    /// up to date as ids are requested and returned.
     pub fn used_count(&self) -> usize {
         self.used
     }
 
+    /// Gets the total count of ids handed out over the pool's
+    /// lifetime.
+    ///
+    /// Unlike the used count, this is never decremented when
+    /// ids are returned.
+    pub fn total_allocations(&self) -> u64 {
+        self.total_allocations
+    }
+
+    /// Gets the current version of the pool, which is bumped by
+    /// every operation that allocates or frees ids.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Gets the used count at the given version, or `None` if
+    /// the version is unknown.
+    ///
+    /// Used counts are kept for the current version and the
+    /// last few before it, which makes it cheap to compute the
+    /// allocation rate between two sampled versions.
+    pub fn used_at_version(&self, version: u64) -> Option<usize> {
+        if version == self.version {
+            return Some(self.used);
+        }
+        self.history
+            .iter()
+            .find(|&&(v, _)| v == version)
+            .map(|&(_, used)| used)
+    }
+
+    /// Gets the count of id requests that found the pool
+    /// exhausted over the pool's lifetime.
+    pub fn exhaustion_count(&self) -> u64 {
+        self.exhaustion_count
+    }
+
+    /// Gets the current count of free ids.
+    ///
+    /// Runs in time linear to the number of free ranges,
+    /// regardless of how many ids the ranges span.
+    pub fn available(&self) -> Num {
+        self.free.iter().map(|range| range.len()).sum()
+    }
+
+    /// Returns a new id or `Err(IdPoolError::Exhausted)` if
+    /// there are no free ids in the pool.
+    ///
+    /// The error carries the capacity of the pool and the count
+    /// of used ids, showing how full the pool was, which may be
+    /// less than the capacity if some ids are blocked.
+    pub fn try_request_id(&mut self) -> Result<Num, IdPoolError> {
+        self.request_id().ok_or(IdPoolError::Exhausted {
+            capacity: self.range.len(),
+            used: self.used,
+        })
+    }
+
+    /// Gets the lowest free id, which is exactly the id the next
+    /// call to [`request_id`] will return, or `None` if there are
+    /// no free ids in the pool.
+    ///
+    /// [`request_id`]: #method.request_id
+    pub fn lowest_available(&self) -> Option<Num> {
+        if !self.can_allocate(1) {
+            return None;
+        }
+        self.free
+            .last()
+            .map(|range| range.start)
+            .filter(|&id| !matches!(self.ceiling, Some(ceiling) if id >= ceiling))
+    }
+
+    /// Earmarks `n` free ids for a subsequent batch, without
+    /// allocating them. Returns `false` if there are not enough
+    /// free ids that aren't earmarked already.
+    ///
+    /// This is a soft reservation: while ids are earmarked, no
+    /// request, be it [`request_id`], [`request_ids`] or any other
+    /// one, drops the count of free ids below the earmarked count.
+    /// Release the earmark with [`release_earmark`] right before
+    /// allocating the batch.
+    ///
+    /// [`request_id`]: #method.request_id
+    /// [`request_ids`]: #method.request_ids
+    /// [`release_earmark`]: #method.release_earmark
+    pub fn try_reserve_capacity(&mut self, n: Num) -> bool {
+        if self.available().saturating_sub(self.earmarked) < n {
+            return false;
+        }
+        self.earmarked += n;
+        true
+    }
+
+    /// Releases `n` earmarked ids, making them available to all
+    /// requests again.
+    pub fn release_earmark(&mut self, n: Num) {
+        self.earmarked = self.earmarked.saturating_sub(n);
+    }
+
+    /// Gets the count of free ids currently earmarked with
+    /// [`try_reserve_capacity`].
+    ///
+    /// [`try_reserve_capacity`]: #method.try_reserve_capacity
+    pub fn earmarked(&self) -> Num {
+        self.earmarked
+    }
+
+    /// Sets a low watermark, invoking `callback` the first time
+    /// the count of free ids drops below `threshold`.
+    ///
+    /// The watermark is re-armed once the count of free ids gets
+    /// back to at least `threshold`, so the callback fires once
+    /// per crossing. The availability is checked after each
+    /// operation that allocates or frees ids. The callback is
+    /// shared between clones of the pool and is not serialized.
+    pub fn set_low_watermark(&mut self, threshold: Num, callback: impl FnMut() + Send + 'static) {
+        self.watermark = Some(Watermark {
+            threshold,
+            callback: Arc::new(Mutex::new(callback)),
+            armed: self.available() >= threshold,
+        });
+    }
+
+    /// Removes the low watermark set with [`set_low_watermark`].
+    ///
+    /// [`set_low_watermark`]: #method.set_low_watermark
+    pub fn clear_low_watermark(&mut self) {
+        self.watermark = None;
+    }
+
+    /// Sets the allocation ceiling, so that [`request_id`] and
+    /// the other requests picking ids themselves never hand out
+    /// ids at or above it, even if they're free. `None` removes
+    /// the ceiling.
+    ///
+    /// The ids above the ceiling stay free and become available
+    /// again once the ceiling is raised or removed.
+    ///
+    /// [`request_id`]: #method.request_id
+    pub fn set_allocation_ceiling(&mut self, ceiling: Option<Num>) {
+        self.ceiling = ceiling;
+    }
+
+    /// Gets the allocation ceiling set with
+    /// [`set_allocation_ceiling`].
+    ///
+    /// [`set_allocation_ceiling`]: #method.set_allocation_ceiling
+    pub fn allocation_ceiling(&self) -> Option<Num> {
+        self.ceiling
+    }
+
+    /// Gets a snapshot of the occupancy and fragmentation of
+    /// the pool.
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            used: self.used,
+            available: self.available(),
+            free_ranges: self.free.len(),
+            largest_free_range: self.free.iter().map(|range| range.len()).max().unwrap_or(0),
+        }
+    }
+
+    /// Replays the given operations against a copy of the pool,
+    /// reporting the stats the pool would end up with, while
+    /// leaving the pool itself untouched.
+    ///
+    /// Operations that would fail, like requests from an exhausted
+    /// pool or returns of free ids, are skipped.
+    pub fn simulate(&self, ops: &[Op]) -> PoolStats {
+        let mut pool = self.clone();
+        pool.apply(ops);
+        pool.stats()
+    }
+
     /// Returns a new id or `None` if there are no free ids
     /// in the pool.
+    ///
+    /// The lowest free id is always handed out first, whether
+    /// it's a recycled one or lies above all allocated ids, see
+    /// [`lowest_available`].
+    ///
+    /// [`lowest_available`]: #method.lowest_available
     pub fn request_id(&mut self) -> Option<Num> {
-        // short-circuit if there are no free ranges
-        if self.free.len() == 0 {
+        #[cfg(feature = "hdrhistogram")]
+        let started = std::time::Instant::now();
+        let id = self.request_lowest();
+        #[cfg(feature = "hdrhistogram")]
+        self.record_latency(started);
+        id
+    }
+
+    /// Allocates the lowest free id, the untimed part of
+    /// [`request_id`].
+    ///
+    /// [`request_id`]: #method.request_id
+    fn request_lowest(&mut self) -> Option<Num> {
+        // short-circuit if there are no free ranges below the ceiling
+        if self.lowest_available().is_none() {
+            self.exhaustion_count += 1;
             return None;
         }
         // always work on the last range on the list
-        let range = self.free.last_mut().unwrap();
+        let mut range = self.free.last().unwrap();
         // get the first number from the range
         let id = range.start;
         // increment range starting point
         range.start += 1;
         // if we have just emptied the range then pop it from the list
-        if range.len() == 0 {
+        if range.is_empty() {
             self.free.pop();
+        } else {
+            self.free.set_last(range);
         }
         self.used += 1;
+        self.total_allocations += 1;
+        #[cfg(debug_assertions)]
+        assert!(self.issued.insert(id), "id {} handed out twice", id);
+        self.record_version();
         Some(id)
     }
 
-    /// Returns an id to the pool or `Err(Num)` if the
-    /// id is already in the pool.
-    pub fn return_id(&mut self, id: Num) -> Result<(), Num> {
-        // search stored ranges for the id in question
-        let position = self.free.binary_search_by(|range| {
-            // match if the id value is adjacent to the range
-            if range.start.checked_sub(1) == Some(id) || range.end == id {
-                std::cmp::Ordering::Equal
+    /// Returns a closure requesting a new id from the pool on each
+    /// call, for plugging the pool into code that expects a
+    /// generator function, like `std::iter::from_fn`.
+    ///
+    /// ```
+    /// # use id_pool::IdPool;
+    /// let mut pool = IdPool::new();
+    /// let ids: Vec<_> = std::iter::from_fn(pool.allocator()).take(3).collect();
+    /// assert_eq!(vec![1, 2, 3], ids);
+    /// ```
+    pub fn allocator(&mut self) -> impl FnMut() -> Option<Num> + '_ {
+        move || self.request_id()
+    }
+
+    /// Returns a new id if `accept` approves of it, or `None` if
+    /// it doesn't or there are no free ids in the pool.
+    ///
+    /// The candidate id is the one [`request_id`] would return.
+    /// It's only allocated once `accept` returns `true`, which
+    /// lets callers check it against an external table first.
+    /// A rejected candidate leaves the pool untouched.
+    ///
+    /// [`request_id`]: #method.request_id
+    pub fn request_id_if<F: FnMut(Num) -> bool>(&mut self, mut accept: F) -> Option<Num> {
+        match self.lowest_available() {
+            Some(id) if !accept(id) => None,
+            _ => self.request_id(),
+        }
+    }
+
+    /// Returns a new id for one of `consumer_count` consumers
+    /// sharing the pool, or `None` if there are no free ids.
+    ///
+    /// The lowest free id congruent to `consumer` modulo
+    /// `consumer_count` is preferred, which keeps the sets of ids
+    /// held by different consumers apart. Once that residue
+    /// class runs out, any free id is handed out instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `consumer` is not less than `consumer_count`.
+    pub fn request_for(&mut self, consumer: usize, consumer_count: usize) -> Option<Num> {
+        assert!(consumer < consumer_count, "consumer index out of bounds");
+        // widened so that counts beyond `Num` neither truncate nor
+        // overflow, in which case only `consumer` itself qualifies
+        let (residue, count) = (consumer as u128, consumer_count as u128);
+        let end = self.ceiling.unwrap_or(Num::MAX) as u128;
+        let preferred = self.free.iter().rev().find_map(|range| {
+            let start = range.start as u128;
+            let id = start + (residue + count - start % count) % count;
+            if id < range.end as u128 && id < end {
+                Some(id as Num)
+            } else {
+                None
             }
-            // match if the id value is contained within the range
-            else if range.contains(&id) {
-                std::cmp::Ordering::Equal
+        });
+        match preferred {
+            Some(id) if self.can_allocate(1) && self.allocate(id) => Some(id),
+            _ => self.request_id(),
+        }
+    }
+
+    /// Returns a new id along with the index, in the order of
+    /// [`free_ranges`], of the free range it was carved from, or
+    /// `None` if there are no free ids in the pool.
+    ///
+    /// The index refers to the free ranges as they were before
+    /// the request. Indices shift whenever ranges are emptied,
+    /// split or merged, so they're only meaningful until the next
+    /// operation on the pool. Since the lowest free id is handed
+    /// out, this is currently always the first range.
+    ///
+    /// [`free_ranges`]: #method.free_ranges
+    pub fn request_id_with_source(&mut self) -> Option<(Num, usize)> {
+        let id = self.lowest_available()?;
+        let index = self.free.len() - 1 - self.free.partition_point(|range| range.start > id);
+        self.request_id().map(|id| (id, index))
+    }
+
+    /// Returns a new id along with the count of ids still free
+    /// after the request, or `None` if there are no free ids in
+    /// the pool.
+    ///
+    /// This lets callers back off as the pool nears exhaustion
+    /// without a separate call to [`available`].
+    ///
+    /// [`available`]: #method.available
+    pub fn request_id_with_remaining(&mut self) -> Option<(Num, Num)> {
+        let id = self.request_id()?;
+        Some((id, self.available()))
+    }
+
+    /// Returns the highest free id, for long-lived objects, or
+    /// `None` if there are no free ids in the pool.
+    ///
+    /// Allocating pinned ids from the top while [`request_id`]
+    /// allocates from the bottom keeps the churn of short-lived
+    /// ids away from the long-lived ones, reducing fragmentation
+    /// of the low part of the range.
+    ///
+    /// [`request_id`]: #method.request_id
+    pub fn request_pinned(&mut self) -> Option<Num> {
+        let ceiling = self.ceiling.unwrap_or(Num::MAX);
+        let id = match self.free.iter().find(|range| range.start < ceiling) {
+            Some(range) if self.can_allocate(1) => range.end.min(ceiling) - 1,
+            _ => {
+                self.exhaustion_count += 1;
+                return None;
             }
-            // otherwise indicate the match must be closer to the id value
-            else {
-                id.cmp(&range.start)
+        };
+        self.allocate(id);
+        Some(id)
+    }
+
+    /// Returns a new id wrapped in an [`AllocatedId`], or `None`
+    /// if there are no free ids in the pool.
+    ///
+    /// [`AllocatedId`]: struct.AllocatedId.html
+    pub fn request_allocated(&mut self) -> Option<AllocatedId> {
+        self.request_id().map(|id| AllocatedId { id })
+    }
+
+    /// Returns a new id, growing the configured range according
+    /// to the growth policy whenever [`request_id`] would come up
+    /// empty, including when the free ids left are earmarked.
+    /// Returns `None` if no growth policy is set, growing the
+    /// range would overflow `Num` or the allocation ceiling keeps
+    /// the new ids out of reach.
+    ///
+    /// Growth is capped at the end of the `Num` space, so near
+    /// it the pool grows by less than the policy asks for.
+    ///
+    /// [`request_id`]: #method.request_id
+    pub fn request_id_or_grow(&mut self) -> Option<Num> {
+        let reachable = !matches!(self.ceiling, Some(ceiling) if ceiling <= self.range.end);
+        if self.lowest_available().is_none() && reachable {
+            let growth = self.growth?.growth(self.range.len());
+            let new_end = self.range.end.saturating_add(growth);
+            self.grow_to(new_end).ok()?;
+        }
+        self.request_id()
+    }
+
+    /// Returns a new id as a non-zero integer or `None` if there
+    /// are no free ids in the pool.
+    ///
+    /// Thanks to the niche left by zero, `Option` of the returned
+    /// id takes up no more space than a plain `Num`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool hands out `0`, which is only possible
+    /// if its range includes `0`. Pools created with [`new`] or
+    /// [`try_new_nonzero`] never do.
+    ///
+    /// [`new`]: #method.new
+    /// [`try_new_nonzero`]: #method.try_new_nonzero
+    pub fn request_nonzero(&mut self) -> Option<NonZeroNum> {
+        self.request_id()
+            .map(|id| NonZeroNum::new(id).expect("pool range includes 0"))
+    }
+
+    /// Returns an id to the pool or `Err(Num)` if the id is
+    /// already in the pool, is blocked or reserved, or lies
+    /// outside of the configured range.
+    pub fn return_id(&mut self, id: Num) -> Result<(), Num> {
+        #[cfg(feature = "hdrhistogram")]
+        let started = std::time::Instant::now();
+        let result = self.release_id(id);
+        #[cfg(feature = "hdrhistogram")]
+        self.record_latency(started);
+        result
+    }
+
+    /// Frees an allocated id, the untimed part of [`return_id`].
+    ///
+    /// [`return_id`]: #method.return_id
+    fn release_id(&mut self, id: Num) -> Result<(), Num> {
+        // ids outside of the range, blocked and reserved ids are
+        // never handed out so they can't be returned
+        if id < self.range.start
+            || id >= self.range.end
+            || self.blocked.contains(&id)
+            || self.is_reserved(id)
+        {
+            return Err(id);
+        }
+        self.insert_free(id)?;
+        self.used -= 1;
+        self.record_version();
+        self.compact_over_threshold();
+        Ok(())
+    }
+
+    /// Returns an id to the pool, silently ignoring ids lying
+    /// outside of the configured range.
+    ///
+    /// This is the forgiving counterpart to [`return_id`], for
+    /// integrations where stray ids may come in from upstream.
+    /// Ids within the range are handled just like with
+    /// [`return_id`], so returning a free id is still an error.
+    ///
+    /// [`return_id`]: #method.return_id
+    pub fn saturating_return(&mut self, id: Num) -> Result<(), Num> {
+        if id < self.range.start || id >= self.range.end {
+            return Ok(());
+        }
+        self.return_id(id)
+    }
+
+    /// Returns an id to the pool, reporting the free range that
+    /// contains the id after it was merged with its neighbours.
+    ///
+    /// Returns `Err(IdPoolError::OutOfRange)` if the id lies
+    /// outside of the configured range or is reserved, or
+    /// `Err(IdPoolError::NotAllocated)` if the id is already in
+    /// the pool or is blocked.
+    pub fn return_id_reporting(&mut self, id: Num) -> Result<std::ops::Range<Num>, IdPoolError> {
+        self.check_allocated(id)?;
+        let mut i = self.insert_free(id).map_err(IdPoolError::NotAllocated)?;
+        self.used -= 1;
+        self.record_version();
+        if self.compact_over_threshold() {
+            i = self.free.partition_point(|range| range.start > id);
+        }
+        Ok(self.free.get(i).unwrap().into())
+    }
+
+    /// Makes the set of used ids match `authoritative_used`, an
+    /// external source of truth, reporting the adjustments.
+    ///
+    /// Ids used in the pool but missing from the list are freed,
+    /// while listed ids that are free in the pool are reserved.
+    /// Listed ids the pool can't hand out at all, because they
+    /// lie outside of the configured range or are blocked or
+    /// reserved, are ignored. Duplicates are allowed.
+    pub fn reconcile(&mut self, authoritative_used: &[Num]) -> ReconcileReport {
+        let mut ids = authoritative_used.to_vec();
+        ids.sort_unstable();
+        ids.dedup();
+        let mut report = ReconcileReport::default();
+        ids.retain(|&id| {
+            let usable = id >= self.range.start
+                && id < self.range.end
+                && !self.blocked.contains(&id)
+                && !self.is_reserved(id);
+            if !usable {
+                report.ignored += 1;
             }
+            usable
         });
-        match position {
-            // range containing id in question was not found,
-            // insert a new range that includes the returned id
-            // at a point in the list that is closest to the id value
-            Err(i) => self.free.insert(
-                i,
-                Range {
-                    start: id,
-                    end: id + 1,
-                },
-            ),
-            // found range adjacent to or containing the id in question
-            Ok(i) => {
-                let range = &mut self.free[i];
-                // id value adjacent to range start point
-                if range.start.checked_sub(1) == Some(id) {
-                    range.start = id;
+        // used ids not covered by the authoritative ones
+        for used in self.used_ranges() {
+            let mut start = used.start;
+            let from = ids.partition_point(|&id| id < used.start);
+            for &id in ids[from..].iter().take_while(|&&id| id < used.end) {
+                if id > start {
+                    report.freed.push(start..id);
                 }
-                // id value adjacent to range end point
-                else if range.end == id {
-                    range.end = range.end + 1;
+                start = id + 1;
+            }
+            if used.end > start {
+                report.freed.push(start..used.end);
+            }
+        }
+        // authoritative ids that are free, walking the free ranges
+        // alongside them
+        {
+            let mut free = self.free_ranges().peekable();
+            for &id in &ids {
+                while free.next_if(|range| range.end <= id).is_some() {}
+                if !matches!(free.peek(), Some(range) if range.start <= id) {
+                    continue;
                 }
-                // id value contained within one of the ranges,
-                // can't return id to the pool
-                else {
-                    return Err(id);
+                match report.reserved.last_mut() {
+                    Some(range) if range.end == id => range.end += 1,
+                    _ => report.reserved.push(id..id + 1),
                 }
-                // check if there exists a range before the current one
-                if let Some(before_range_idx) = i.checked_sub(1) {
-                    // if the current range's end point is the previous range's
-                    // start point, then merge the ranges into one
-                    if self.free[before_range_idx].start == self.free[i].end {
-                        self.free[before_range_idx].start = self.free[i].start;
-                        self.free.remove(i);
-                    }
+            }
+        }
+        if report.freed.is_empty() && report.reserved.is_empty() {
+            self.debug_assert_consistent();
+            return report;
+        }
+        for range in &report.freed {
+            self.used -= (range.end - range.start) as usize;
+            self.forget_issued(range.clone());
+        }
+        for range in &report.reserved {
+            self.used += (range.end - range.start) as usize;
+            self.total_allocations += (range.end - range.start) as u64;
+        }
+        // the used ids are now exactly the authoritative ones, so
+        // the free ranges are the gaps between them and the
+        // withheld ids, rebuilt in a single pass
+        let mut taken = self.withheld(self.range.start, self.range.end);
+        taken.extend(ids.iter().map(|&id| id..id + 1));
+        taken.sort_unstable_by_key(|range| range.start);
+        let mut free = Vec::new();
+        let mut start = self.range.start;
+        for range in taken {
+            if range.start > start {
+                free.push(Range {
+                    start,
+                    end: range.start,
+                });
+            }
+            start = start.max(range.end);
+        }
+        if self.range.end > start {
+            free.push(Range {
+                start,
+                end: self.range.end,
+            });
+        }
+        free.reverse();
+        self.free.replace(free);
+        self.record_version();
+        self.debug_assert_consistent();
+        report
+    }
+
+    /// Returns all of the given ids to the pool, reporting how
+    /// each of them was handled.
+    ///
+    /// Unlike [`reserve_many`], the batch is not validated up
+    /// front. Ids that can't be returned are skipped and counted
+    /// in the report, while the rest are returned.
+    ///
+    /// [`reserve_many`]: #method.reserve_many
+    pub fn bulk_return(&mut self, ids: &[Num]) -> BulkReturnReport {
+        let mut report = BulkReturnReport::default();
+        for &id in ids {
+            if id < self.range.start || id >= self.range.end {
+                report.out_of_range += 1;
+            } else if self.blocked.contains(&id) || self.is_reserved(id) {
+                report.withheld += 1;
+            } else {
+                let ranges = self.free.len();
+                if self.insert_free(id).is_ok() {
+                    self.used -= 1;
+                    self.record_version();
+                    report.returned += 1;
+                    // each merge leaves one range less than inserting
+                    // the id separately would
+                    report.merges += ranges + 1 - self.free.len();
+                } else {
+                    report.already_free += 1;
                 }
             }
         }
+        self.debug_assert_consistent();
+        report
+    }
+
+    /// Replaces an allocated id with a new one, returning `old`
+    /// to the pool and handing out a new id in a single call.
+    ///
+    /// The new id is allocated before `old` becomes free, so it
+    /// is guaranteed to differ from `old`. Returns an error if
+    /// `old` is not allocated, or `Err(IdPoolError::Exhausted)`
+    /// if there are no other free ids, leaving the pool intact.
+    pub fn swap_id(&mut self, old: Num) -> Result<Num, IdPoolError> {
+        self.check_allocated(old)?;
+        let new = self.try_request_id()?;
+        // the id was checked to be allocated, so this can't fail
+        let _ = self.insert_free(old);
         self.used -= 1;
+        self.record_version();
+        Ok(new)
+    }
+
+    /// Blocks an id, making sure it won't be handed out by
+    /// subsequent requests until it's unblocked. Returns
+    /// `Err(Num)` if the id is not currently free.
+    pub fn block_id(&mut self, id: Num) -> Result<(), Num> {
+        if !self.remove_free(id) {
+            return Err(id);
+        }
+        self.blocked.insert(id);
+        self.debug_assert_consistent();
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Unblocks a previously blocked id, making it available
+    /// for allocation again. Returns `Err(Num)` if the id
+    /// was not blocked.
+    pub fn unblock_id(&mut self, id: Num) -> Result<(), Num> {
+        if !self.blocked.remove(&id) {
+            return Err(id);
+        }
+        self.insert_free(id)?;
+        self.debug_assert_consistent();
+        Ok(())
+    }
 
-    #[test]
-    fn request() {
-        let mut pool = IdPool::new();
-        assert_eq!(Some(1), pool.request_id());
-        assert_eq!(Some(2), pool.request_id());
-        assert_eq!(Some(3), pool.request_id());
+    /// Checks whether the given id is currently free.
+    ///
+    /// Runs in time logarithmic to the number of free ranges.
+    pub fn is_free(&self, id: Num) -> bool {
+        let i = self.free.partition_point(|range| range.start > id);
+        matches!(self.free.get(i), Some(range) if range.end > id)
     }
 
-    #[test]
-    fn request_return() {
-        let mut pool = IdPool::new();
-        assert_eq!(Some(1), pool.request_id());
-        assert_eq!(Some(2), pool.request_id());
-        assert_eq!(Some(3), pool.request_id());
-        assert_eq!(Ok(()), pool.return_id(1));
-        assert_eq!(Some(1), pool.request_id());
-        assert_eq!(Ok(()), pool.return_id(2));
-        assert_eq!(Some(2), pool.request_id());
-        assert_eq!(Some(4), pool.request_id());
-        assert_eq!(Ok(()), pool.return_id(3));
-        assert_eq!(Ok(()), pool.return_id(4));
-        assert_eq!(Err(5), pool.return_id(5));
+    /// Checks whether the given id is currently blocked.
+    ///
+    /// Runs in time logarithmic to the number of blocked ids.
+    pub fn is_blocked(&self, id: Num) -> bool {
+        self.blocked.contains(&id)
     }
 
-    #[test]
-    fn used_count() {
-        let mut pool = IdPool::new_ranged(1..10);
-        assert_eq!(Some(1), pool.request_id());
-        assert_eq!(Some(2), pool.request_id());
-        assert_eq!(Some(3), pool.request_id());
-        assert_eq!(Ok(()), pool.return_id(1));
-        assert_eq!(2, pool.used_count());
+    /// Checks whether the given id lies in one of the spans
+    /// reserved with [`with_reserved_ranges`].
+    ///
+    /// [`with_reserved_ranges`]: #method.with_reserved_ranges
+    pub fn is_reserved(&self, id: Num) -> bool {
+        let i = self.reserved.partition_point(|range| range.end <= id);
+        matches!(self.reserved.get(i), Some(range) if range.start <= id)
     }
 
-    #[test]
-    fn return_last() {
-        let mut pool = IdPool::new_ranged(1..10);
+    /// Gets the current count of free ranges.
+    pub fn free_range_count(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Returns an iterator over all the free ranges, in
+    /// ascending order.
+    pub fn free_ranges(&self) -> impl Iterator<Item = std::ops::Range<Num>> + '_ {
+        self.free.iter().rev().map(|range| range.start..range.end)
+    }
+
+    /// Renders the free ranges in a compact human-readable
+    /// form, such as `"[1-5, 9, 100-200]"`.
+    ///
+    /// Ranges are listed in ascending order with inclusive
+    /// bounds, single-id ranges are rendered as just the id.
+    pub fn free_ranges_string(&self) -> String {
+        format_ranges(self.free_ranges())
+    }
+
+    /// Returns an iterator over the used ids in ascending order,
+    /// grouped into chunks of at most `chunk_size` ids.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn used_chunks(&self, chunk_size: usize) -> impl Iterator<Item = Vec<Num>> {
+        assert!(chunk_size != 0, "chunk size must be non-zero");
+        let mut ids = self.used_ranges().into_iter().flatten();
+        std::iter::from_fn(move || {
+            let chunk: Vec<Num> = ids.by_ref().take(chunk_size).collect();
+            if chunk.is_empty() {
+                None
+            } else {
+                Some(chunk)
+            }
+        })
+    }
+
+    /// Renders the runs of used ids in the same compact form
+    /// as [`free_ranges_string`], such as `"[1-3, 7-9]"`.
+    ///
+    /// [`free_ranges_string`]: #method.free_ranges_string
+    pub fn used_ranges_string(&self) -> String {
+        format_ranges(self.used_ranges().into_iter())
+    }
+
+    /// Creates a pool over the `configured` range with the free
+    /// ranges parsed from the form produced by
+    /// [`free_ranges_string`]. All other ids in the configured
+    /// range are considered used.
+    ///
+    /// Returns `Err(IdPoolError::InvalidFormat)` if the string
+    /// is malformed, or `Err(IdPoolError::InvalidRange)` if the
+    /// ranges are out of order or don't fit in the configured
+    /// range.
+    ///
+    /// [`free_ranges_string`]: #method.free_ranges_string
+    pub fn from_ranges_string(
+        configured: std::ops::Range<Num>,
+        s: &str,
+    ) -> Result<Self, IdPoolError> {
+        let mut pool = Self::try_new_ranged(configured)?;
+        pool.free.clear();
+        let inner = s
+            .trim()
+            .strip_prefix('[')
+            .and_then(|s| s.strip_suffix(']'))
+            .ok_or(IdPoolError::InvalidFormat)?;
+        let mut min = pool.range.start;
+        for part in inner.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let mut bounds = part.splitn(2, '-').map(|b| b.trim().parse::<Num>());
+            let start = match bounds.next() {
+                Some(Ok(start)) => start,
+                _ => return Err(IdPoolError::InvalidFormat),
+            };
+            let last = match bounds.next() {
+                Some(Ok(last)) => last,
+                Some(Err(_)) => return Err(IdPoolError::InvalidFormat),
+                None => start,
+            };
+            // ranges must be ascending, disjoint and within bounds
+            if start < min || last < start || last >= pool.range.end {
+                return Err(IdPoolError::InvalidRange);
+            }
+            pool.insert_free_range(start, last + 1);
+            min = last + 1;
+        }
+        pool.used = (pool.range.len() - pool.available()) as usize;
+        pool.debug_assert_consistent();
+        Ok(pool)
+    }
+
+    /// Gets the `n`-th free id, counting from zero in ascending
+    /// order, or `None` if there are not enough free ids.
+    ///
+    /// Runs in time linear to the number of free ranges.
+    pub fn nth_free(&self, n: Num) -> Option<Num> {
+        let mut n = n;
+        for range in self.free.iter().rev() {
+            if n < range.len() {
+                return Some(range.start + n);
+            }
+            n -= range.len();
+        }
+        None
+    }
+
+    /// Returns the lowest free id of each free range, in
+    /// ascending order.
+    ///
+    /// This characterizes the free space cheaply, for example
+    /// to seed randomized tests touching every range.
+    pub fn sample_boundaries(&self) -> Vec<Num> {
+        self.free.iter().rev().map(|range| range.start).collect()
+    }
+
+    /// Returns an iterator over the free ranges lying between
+    /// allocated ids, in ascending order.
+    ///
+    /// The trailing free range above the highest allocated
+    /// id is not included, which makes it possible to tell
+    /// apart fragmentation from unallocated tail space.
+    /// Iterating over all the gaps takes time linear to the
+    /// number of free ranges.
+    pub fn gaps(&self) -> impl Iterator<Item = std::ops::Range<Num>> + '_ {
+        self.free
+            .iter()
+            .rev()
+            .filter(move |range| range.end != self.range.end)
+            .map(|range| range.start..range.end)
+    }
+
+    /// Returns a contiguous block of `count` new ids or `None`
+    /// if there is no free range large enough to fit it.
+    ///
+    /// The block is taken from the lowest free range that
+    /// can accommodate it.
+    pub fn request_contiguous(&mut self, count: Num) -> Option<std::ops::Range<Num>> {
+        if !self.can_allocate(count as usize) {
+            return None;
+        }
+        let ceiling = self.ceiling.unwrap_or(Num::MAX);
+        // search the ranges starting from the lowest one
+        let i = self.free.iter().rposition(|range| {
+            range.start < ceiling && range.end.min(ceiling) - range.start >= count
+        })?;
+        let mut range = self.free.get(i).unwrap();
+        let block = range.start..range.start + count;
+        range.start += count;
+        if range.is_empty() {
+            self.free.remove(i);
+        } else {
+            self.free.set(i, range);
+        }
+        self.used += count as usize;
+        self.record_version();
+        self.total_allocations += count as u64;
+        self.debug_assert_consistent();
+        Some(block)
+    }
+
+    /// Returns `count` new ids or `None` if there are not enough
+    /// free ids in the pool.
+    ///
+    /// Ids are taken from the lowest free ranges, so they are
+    /// not necessarily contiguous. Whole runs of ids are carved
+    /// out of the ranges at once, making this considerably
+    /// faster than requesting ids one by one.
+    pub fn request_ids(&mut self, count: usize) -> Option<Vec<Num>> {
+        if !self.can_allocate(count) || self.free_below_ceiling() < count {
+            return None;
+        }
+        let mut ids = Vec::with_capacity(count);
+        while ids.len() < count {
+            // there are enough free ids below the ceiling so the
+            // ranges won't run out
+            let mut range = self.free.last().unwrap();
+            let take = range.len().min((count - ids.len()) as Num);
+            ids.extend(range.start..range.start + take);
+            range.start += take;
+            if range.is_empty() {
+                self.free.pop();
+            } else {
+                self.free.set_last(range);
+            }
+        }
+        self.used += count;
+        self.record_version();
+        self.total_allocations += count as u64;
+        self.debug_assert_consistent();
+        Some(ids)
+    }
+
+    /// Returns `count` new ids spaced at least `stride` apart, or
+    /// `None` if there are not enough free ids with that spacing.
+    ///
+    /// Ids are picked greedily starting from the lowest free id,
+    /// each one being the lowest free id at least `stride` above
+    /// the previous one, which finds a spacing whenever one
+    /// exists.
+    pub fn request_strided(&mut self, count: usize, stride: Num) -> Option<Vec<Num>> {
+        if !self.can_allocate(count) {
+            return None;
+        }
+        let stride = stride.max(1);
+        let ceiling = self.ceiling.unwrap_or(Num::MAX);
+        let mut ids = Vec::with_capacity(count);
+        let mut next = self.range.start;
+        // ranges are in descending order, walk them from the lowest
+        'ranges: for range in self.free.iter().rev() {
+            let mut id = range.start.max(next);
+            while id < range.end.min(ceiling) {
+                if ids.len() == count {
+                    break 'ranges;
+                }
+                ids.push(id);
+                next = match id.checked_add(stride) {
+                    Some(next) => next,
+                    None => break 'ranges,
+                };
+                id = next;
+            }
+        }
+        if ids.len() < count {
+            return None;
+        }
+        for &id in &ids {
+            self.remove_free(id);
+        }
+        self.used += count;
+        self.record_version();
+        self.total_allocations += count as u64;
+        self.debug_assert_consistent();
+        Some(ids)
+    }
+
+    /// Returns `count` new ids all lying within a window of at
+    /// most `max_span` consecutive values, or `None` if no such
+    /// window holds enough free ids.
+    ///
+    /// The ids are not necessarily contiguous, but clustered
+    /// closely enough for good locality. Windows are considered
+    /// from the low end of the pool, either starting at a free
+    /// range or ending with one, which covers the densest windows.
+    pub fn request_ids_within_span(&mut self, count: usize, max_span: Num) -> Option<Vec<Num>> {
+        if !self.can_allocate(count) {
+            return None;
+        }
+        if count == 0 {
+            return Some(Vec::new());
+        }
+        let ceiling = self.ceiling.unwrap_or(Num::MAX);
+        let ranges: Vec<std::ops::Range<Num>> = self
+            .free_ranges()
+            .take_while(|range| range.start < ceiling)
+            .map(|range| range.start..range.end.min(ceiling))
+            .collect();
+        // free ids below each of the ranges
+        let mut below = Vec::with_capacity(ranges.len() + 1);
+        below.push(0);
+        for range in &ranges {
+            below.push(below.last().unwrap() + (range.end - range.start) as usize);
+        }
+        let free_within = |start: Num, end: Num| {
+            let i = ranges.partition_point(|range| range.end <= start);
+            let j = ranges.partition_point(|range| range.start < end);
+            if i >= j {
+                return 0;
+            }
+            below[j]
+                - below[i]
+                - start.saturating_sub(ranges[i].start) as usize
+                - ranges[j - 1].end.saturating_sub(end) as usize
+        };
+        let mut starts: Vec<Num> = ranges
+            .iter()
+            .flat_map(|range| [range.start, range.end.saturating_sub(max_span)])
+            .collect();
+        starts.sort_unstable();
+        let start = starts
+            .into_iter()
+            .find(|&start| free_within(start, start.saturating_add(max_span)) >= count)?;
+        let ids: Vec<Num> = ranges
+            .iter()
+            .flat_map(|range| range.start.max(start)..range.end)
+            .take(count)
+            .collect();
+        for &id in &ids {
+            self.remove_free(id);
+        }
+        self.used += count;
+        self.record_version();
+        self.total_allocations += count as u64;
+        self.debug_assert_consistent();
+        Some(ids)
+    }
+
+    /// Same as [`request_ids`], but also returns a token that
+    /// can be passed to [`rollback_allocation`] to return exactly
+    /// the ids handed out by this call.
+    ///
+    /// [`request_ids`]: #method.request_ids
+    /// [`rollback_allocation`]: #method.rollback_allocation
+    pub fn request_ids_with_token(&mut self, count: usize) -> Option<(Vec<Num>, AllocationToken)> {
+        let ids = self.request_ids(count)?;
+        let mut ranges: Vec<std::ops::Range<Num>> = Vec::new();
+        for &id in &ids {
+            match ranges.last_mut() {
+                Some(range) if range.end == id => range.end += 1,
+                _ => ranges.push(id..id + 1),
+            }
+        }
+        Some((ids, AllocationToken { ranges }))
+    }
+
+    /// Returns all of the ids covered by the token to the pool,
+    /// undoing the allocation that produced it.
+    ///
+    /// The whole token is validated first, so either all of the
+    /// ids get returned or none of them do. Returns
+    /// `Err(IdPoolError::NotAllocated)` if any of the ids has
+    /// been returned, blocked or reserved in the meantime.
+    pub fn rollback_allocation(&mut self, token: AllocationToken) -> Result<(), IdPoolError> {
+        for range in &token.ranges {
+            // checking both ends covers the whole range against the
+            // bounds of the configured range
+            self.check_allocated(range.start)?;
+            self.check_allocated(range.end - 1)?;
+            if let Some(withheld) = self.withheld(range.start, range.end).first() {
+                return Err(IdPoolError::NotAllocated(withheld.start));
+            }
+            if let Some(free) = self
+                .free_ranges()
+                .find(|free| free.start < range.end && free.end > range.start)
+            {
+                return Err(IdPoolError::NotAllocated(free.start.max(range.start)));
+            }
+        }
+        for range in token.ranges {
+            // ranges were checked to be allocated, so this can't fail
+            self.insert_free_range(range.start, range.end);
+            self.used -= (range.end - range.start) as usize;
+        }
+        self.record_version();
+        self.debug_assert_consistent();
+        Ok(())
+    }
+
+    /// Reserves all of the given ids, so that they won't be
+    /// handed out by subsequent requests.
+    ///
+    /// The whole batch is validated before any id is reserved,
+    /// so either all of the ids get reserved or none of them
+    /// do. Returns `Err(IdPoolError::OutOfRange)` for ids lying
+    /// outside of the configured range and
+    /// `Err(IdPoolError::AlreadyAllocated)` for ids that are
+    /// not free, including ids repeated within the batch.
+    /// Returns `Err(IdPoolError::Exhausted)` if reserving the
+    /// batch would dip into earmarked ids.
+    pub fn reserve_many(&mut self, ids: &[Num]) -> Result<(), IdPoolError> {
+        let mut seen = BTreeSet::new();
+        for &id in ids {
+            if id < self.range.start || id >= self.range.end {
+                return Err(IdPoolError::OutOfRange(id));
+            }
+            if !self.is_free(id) || !seen.insert(id) {
+                return Err(IdPoolError::AlreadyAllocated(id));
+            }
+        }
+        if !self.can_allocate(ids.len()) {
+            return Err(IdPoolError::Exhausted {
+                capacity: self.range.len(),
+                used: self.used,
+            });
+        }
+        for &id in ids {
+            self.allocate(id);
+        }
+        self.debug_assert_consistent();
+        Ok(())
+    }
+
+    /// Moves all used ids to the lowest available positions,
+    /// leaving a single contiguous free space above them.
+    ///
+    /// Returns the remapping of old ids to new ids. Ids that
+    /// didn't need to be moved are not included. Blocked and
+    /// reserved ids are never moved and are skipped over when
+    /// packing.
+    pub fn pack(&mut self) -> HashMap<Num, Num> {
+        let mut remap = HashMap::new();
+        let mut target = self.range.start;
+        for range in self.used_ranges() {
+            for id in range {
+                target = self.skip_withheld(target);
+                if id != target {
+                    remap.insert(id, target);
+                }
+                target += 1;
+            }
+        }
+        self.free_from(target);
+        // moved ids are no longer tracked
+        self.forget_issued(self.range.start..target);
+        self.debug_assert_consistent();
+        remap
+    }
+
+    /// Returns a contiguous block of `count` new ids, packing
+    /// the used ids with [`pack`] if the free space is too
+    /// fragmented to fit the block otherwise.
+    ///
+    /// Along with the block, the remapping produced by packing
+    /// is returned, which is empty if packing wasn't necessary.
+    /// Returns `None` without modifying the pool if the block
+    /// wouldn't fit even after packing, taking earmarks, the
+    /// allocation ceiling and the blocked and reserved ids
+    /// splitting the packed free space into account.
+    ///
+    /// [`pack`]: #method.pack
+    pub fn try_request_contiguous_with_pack(
+        &mut self,
+        count: Num,
+    ) -> Option<(std::ops::Range<Num>, HashMap<Num, Num>)> {
+        self.try_request_contiguous_with_pack_budget(count, usize::MAX)
+    }
+
+    /// Same as [`try_request_contiguous_with_pack`], but bails
+    /// out with `None` instead of packing if the free space is
+    /// split into more than `max_ranges` ranges, keeping the
+    /// cost of the call bounded.
+    ///
+    /// [`try_request_contiguous_with_pack`]: #method.try_request_contiguous_with_pack
+    pub fn try_request_contiguous_with_pack_budget(
+        &mut self,
+        count: Num,
+        max_ranges: usize,
+    ) -> Option<(std::ops::Range<Num>, HashMap<Num, Num>)> {
+        if let Some(block) = self.request_contiguous(count) {
+            return Some((block, HashMap::new()));
+        }
+        // packing has to move every used run between the ranges
+        if self.free.len() > max_ranges || !self.fits_once_packed(count) {
+            return None;
+        }
+        let remap = self.pack();
+        let block = self
+            .request_contiguous(count)
+            .expect("block doesn't fit the packed pool");
+        Some((block, remap))
+    }
+
+    /// Extends the end of the configured range to `new_end`,
+    /// adding the new ids to the pool as free.
+    ///
+    /// Returns `Err(IdPoolError::InvalidRange)` if `new_end`
+    /// doesn't lie above the current end of the range.
+    pub fn grow_to(&mut self, new_end: Num) -> Result<(), IdPoolError> {
+        if new_end <= self.range.end {
+            return Err(IdPoolError::InvalidRange);
+        }
+        // ids above the configured range are never free, so
+        // inserting them can't fail
+        self.insert_free_range(self.range.end, new_end);
+        self.range.end = new_end;
+        self.debug_assert_consistent();
+        Ok(())
+    }
+
+    /// Returns a contiguous block of `count` new ids, growing the
+    /// configured range if there is no free range large enough
+    /// to fit it.
+    ///
+    /// The range is grown in increments of `grow_by` ids, just
+    /// enough for the block to fit at the top of the pool. A
+    /// `grow_by` of zero grows the range exactly as much as
+    /// needed. Returns `None` without growing the range if the
+    /// allocation ceiling would keep the new ids out of reach,
+    /// or if growing the range would overflow `Num`.
+    pub fn request_block_or_grow(
+        &mut self,
+        count: Num,
+        grow_by: Num,
+    ) -> Option<std::ops::Range<Num>> {
+        if let Some(block) = self.request_contiguous(count) {
+            return Some(block);
+        }
+        // the highest free range can be extended by growing
+        let tail = match self.free.first() {
+            Some(range) if range.end == self.range.end => range.len(),
+            _ => 0,
+        };
+        // growing also has to make up for ids held back by earmarks
+        let unearmarked = self.available().saturating_sub(self.earmarked);
+        let needed = count
+            .saturating_sub(tail)
+            .max(count.saturating_sub(unearmarked));
+        let reach = self.range.end.checked_add(needed)?;
+        if matches!(self.ceiling, Some(ceiling) if ceiling < reach) {
+            return None;
+        }
+        let step = grow_by.max(1);
+        let growth = needed.checked_add(step - 1)? / step * step;
+        let new_end = self.range.end.checked_add(growth)?;
+        // growth is non-zero since the block didn't fit before
+        self.grow_to(new_end).ok()?;
+        self.request_contiguous(count)
+    }
+
+    /// Raises the start of the configured range to `new_start`,
+    /// removing the ids below it from the pool.
+    ///
+    /// Returns an error if any id below `new_start` is currently
+    /// allocated, or if `new_start` lies outside of the
+    /// configured range.
+    pub fn raise_start(&mut self, new_start: Num) -> Result<(), IdPoolError> {
+        if new_start < self.range.start || new_start > self.range.end {
+            return Err(IdPoolError::InvalidRange);
+        }
+        if let Some(used) = self.used_ranges().first() {
+            if used.start < new_start {
+                return Err(IdPoolError::AlreadyAllocated(used.start));
+            }
+        }
+        // drop the free ranges that end below the new start and
+        // trim the one crossing it
+        self.free.retain(|range| range.end > new_start);
+        if let Some(mut range) = self.free.last() {
+            range.start = range.start.max(new_start);
+            self.free.set_last(range);
+        }
+        self.prune_empty_ranges();
+        self.blocked = self.blocked.split_off(&new_start);
+        self.forget_issued(self.range.start..new_start);
+        self.reserved.retain(|range| range.end > new_start);
+        if let Some(range) = self.reserved.first_mut() {
+            range.start = range.start.max(new_start);
+        }
+        self.range.start = new_start;
+        self.debug_assert_consistent();
+        Ok(())
+    }
+
+    /// Raises the start of the configured range up to the lowest
+    /// allocated id, removing the free ids below it from the pool,
+    /// as with [`raise_start`]. Leaves the pool untouched if no
+    /// ids are allocated.
+    ///
+    /// [`raise_start`]: #method.raise_start
+    pub fn shrink_start_to_lowest_used(&mut self) {
+        if let Some(used) = self.used_ranges().first() {
+            // no id lies below the lowest allocated one
+            self.raise_start(used.start).unwrap();
+        }
+    }
+
+    /// Splits the pool into two at the given id. The returned
+    /// pool takes over the ids from `at` up to the end of the
+    /// configured range, along with their state, while this
+    /// pool keeps the ids below `at`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at` lies outside of the configured range.
+    pub fn split_off(&mut self, at: Num) -> IdPool {
+        assert!(
+            at >= self.range.start && at <= self.range.end,
+            "split point outside of the configured range"
+        );
+        let mut upper =
+            IdPool::new_ranged(at..self.range.end).with_lazy_coalescing(self.lazy_coalescing);
+        // ranges are in descending order, so the upper ones come first
+        let split = self.free.partition_point(|range| range.end > at);
+        let mut free = self.free.split_front(split);
+        if let Some(range) = free.last_mut() {
+            if range.start < at {
+                self.free.insert(
+                    0,
+                    Range {
+                        start: range.start,
+                        end: at,
+                    },
+                );
+                range.start = at;
+            }
+        }
+        upper.free.replace(free);
+        upper.blocked = self.blocked.split_off(&at);
+        #[cfg(debug_assertions)]
+        {
+            upper.issued = self.issued.iter().copied().filter(|&id| id >= at).collect();
+            self.issued.retain(|&id| id < at);
+        }
+        // reserved ranges are in ascending order, so the upper ones come last
+        let split = self.reserved.partition_point(|range| range.end <= at);
+        upper.reserved = self.reserved.split_off(split);
+        if let Some(range) = upper.reserved.first_mut() {
+            if range.start < at {
+                self.reserved.push(Range {
+                    start: range.start,
+                    end: at,
+                });
+                range.start = at;
+            }
+        }
+        self.prune_empty_ranges();
+        upper.prune_empty_ranges();
+        upper.used = (upper.range.len()
+            - upper.available()
+            - upper.blocked.len() as Num
+            - upper.reserved_count()) as usize;
+        self.used -= upper.used;
+        self.range.end = at;
+        self.debug_assert_consistent();
+        upper.debug_assert_consistent();
+        upper
+    }
+
+    /// Moves up to `count` free ids from this pool over to
+    /// `other`, returning how many ids were actually moved.
+    ///
+    /// Both pools are expected to be configured over the same
+    /// space, with each id free in at most one of them. From
+    /// the perspective of each pool, ids held by the other
+    /// one count as used. Ids are taken from the top of the
+    /// free space, the transfer stops early if `other` already
+    /// considers any of them free, blocked or reserved.
+    ///
+    /// Returns `Err(IdPoolError::RangeMismatch)` if the pools
+    /// are configured over different ranges.
+    pub fn reclaim_into(&mut self, other: &mut IdPool, count: Num) -> Result<Num, IdPoolError> {
+        if !self.has_same_range(other) {
+            return Err(IdPoolError::RangeMismatch);
+        }
+        let mut moved = 0;
+        while moved < count {
+            // always work on the highest free range
+            let range = match self.free.first() {
+                Some(range) => range,
+                None => break,
+            };
+            let len = range.len().min(count - moved);
+            let start = range.end - len;
+            if other.used < len as usize || !other.withheld(start, range.end).is_empty() {
+                break;
+            }
+            if other.insert_free_range(start, range.end).is_none() {
+                break;
+            }
+            other.used -= len as usize;
+            if len == range.len() {
+                self.free.remove(0);
+            } else {
+                self.free.set(
+                    0,
+                    Range {
+                        start: range.start,
+                        end: start,
+                    },
+                );
+            }
+            self.prune_empty_ranges();
+            self.used += len as usize;
+            moved += len;
+        }
+        self.debug_assert_consistent();
+        other.debug_assert_consistent();
+        Ok(moved)
+    }
+
+    /// Merges the free ids of `other` into this pool.
+    ///
+    /// As with [`reclaim_into`], both pools are expected to be
+    /// configured over the same space, with each id free in at
+    /// most one of them. All of the ids free in `other` become
+    /// free in this pool. Returns
+    /// `Err(IdPoolError::RangeMismatch)` if the pools are
+    /// configured over different ranges, or
+    /// `Err(IdPoolError::NotAllocated)` if this pool doesn't
+    /// consider one of the ids used, leaving the pool intact.
+    ///
+    /// [`reclaim_into`]: #method.reclaim_into
+    pub fn merge(&mut self, other: IdPool) -> Result<(), IdPoolError> {
+        if !self.has_same_range(&other) {
+            return Err(IdPoolError::RangeMismatch);
+        }
+        // validate the whole merge before touching the pool
+        for range in other.free_ranges() {
+            if let Some(withheld) = self.withheld(range.start, range.end).first() {
+                return Err(IdPoolError::NotAllocated(withheld.start));
+            }
+            if let Some(free) = self
+                .free_ranges()
+                .find(|free| free.start < range.end && free.end > range.start)
+            {
+                return Err(IdPoolError::NotAllocated(free.start.max(range.start)));
+            }
+        }
+        for range in other.free_ranges() {
+            // ranges were checked to not overlap, so this can't fail
+            self.insert_free_range(range.start, range.end);
+            self.used -= (range.end - range.start) as usize;
+        }
+        self.record_version();
+        self.debug_assert_consistent();
+        Ok(())
+    }
+
+    /// Checks whether both pools are configured over the same
+    /// range of ids.
+    pub fn has_same_range(&self, other: &IdPool) -> bool {
+        self.range.start == other.range.start && self.range.end == other.range.end
+    }
+
+    /// Coalesces adjacent free ranges and drops empty ones,
+    /// rewriting the list of free ranges in place.
+    ///
+    /// This never grows the underlying vector, making it
+    /// suitable for a one-shot cleanup pass.
+    pub fn compact_ranges_in_place(&mut self) {
+        let mut write: usize = 0;
+        for read in 0..self.free.len() {
+            let range = self.free.get(read).unwrap();
+            if range.is_empty() {
+                continue;
+            }
+            // ranges are in descending order, so the range being
+            // read can only extend the last written one downwards
+            match write.checked_sub(1).and_then(|i| self.free.get(i)) {
+                Some(mut last) if last.start == range.end => {
+                    last.start = range.start;
+                    self.free.set(write - 1, last);
+                }
+                _ => {
+                    self.free.set(write, range);
+                    write += 1;
+                }
+            }
+        }
+        self.free.truncate(write);
+    }
+
+    /// Returns all used ids for which `pred` returns `true` to
+    /// the pool, leaving the rest allocated.
+    ///
+    /// The freed ids are returned in ascending order.
+    pub fn drain_used_where<F: FnMut(Num) -> bool>(&mut self, mut pred: F) -> Vec<Num> {
+        let drained: Vec<Num> = self
+            .used_ranges()
+            .into_iter()
+            .flatten()
+            .filter(|&id| pred(id))
+            .collect();
+        for &id in &drained {
+            // used ids are never free, so this can't fail
+            let _ = self.insert_free(id);
+        }
+        self.used -= drained.len();
+        self.record_version();
+        self.debug_assert_consistent();
+        drained
+    }
+
+    /// Checks the internal invariants of the pool, panicking
+    /// if any of them is violated.
+    ///
+    /// Free ranges must be non-empty, coalesced (unless lazy
+    /// coalescing is enabled), sorted and contained within the
+    /// configured range, blocked and reserved ids must not be
+    /// free, and all ids in the configured range must be
+    /// accounted for as either free, used, blocked or reserved.
+    pub fn assert_consistent(&self) {
+        for range in &self.free {
+            assert!(!range.is_empty(), "empty free range");
+            assert!(
+                range.start >= self.range.start && range.end <= self.range.end,
+                "free range outside of the configured range"
+            );
+        }
+        for (upper, lower) in self.free.iter().zip(self.free.iter().skip(1)) {
+            assert!(
+                lower.end < upper.start || self.lazy_coalescing && lower.end == upper.start,
+                "free ranges out of order or not coalesced"
+            );
+        }
+        for &id in &self.blocked {
+            assert!(
+                id >= self.range.start && id < self.range.end,
+                "blocked id {} outside of the configured range",
+                id
+            );
+            assert!(
+                self.free
+                    .iter()
+                    .all(|range| id < range.start || id >= range.end),
+                "blocked id {} is free",
+                id
+            );
+        }
+        for pair in self.reserved.windows(2) {
+            assert!(
+                pair[0].end <= pair[1].start,
+                "reserved ranges out of order or overlapping"
+            );
+        }
+        for range in &self.reserved {
+            assert!(!range.is_empty(), "empty reserved range");
+            assert!(
+                range.start >= self.range.start && range.end <= self.range.end,
+                "reserved range outside of the configured range"
+            );
+            assert!(
+                self.free
+                    .iter()
+                    .all(|free| free.end <= range.start || free.start >= range.end),
+                "reserved range {}..{} is free",
+                range.start,
+                range.end
+            );
+            assert!(
+                self.blocked.range(range.start..range.end).next().is_none(),
+                "reserved range {}..{} is blocked",
+                range.start,
+                range.end
+            );
+        }
+        assert_eq!(
+            self.range.len(),
+            self.used as Num + self.available() + self.blocked.len() as Num + self.reserved_count(),
+            "ids not accounted for"
+        );
+    }
+
+    /// Decomposes the pool into the configured range, the list
+    /// of free ranges and the count of used ids.
+    ///
+    /// Free ranges are listed in descending order, the way
+    /// they're stored internally. Blocked and reserved ids are
+    /// not preserved, they are counted as used instead.
+    pub fn into_parts(self) -> (std::ops::Range<Num>, Vec<Range>, usize) {
+        let used = self.used + self.blocked.len() + self.reserved_count() as usize;
+        (self.range.into(), self.free.to_vec(), used)
+    }
+
+    /// Creates a pool from parts produced by [`into_parts`].
+    ///
+    /// Returns `Err(IdPoolError::InvalidRange)` if the configured
+    /// range is inverted, or `Err(IdPoolError::Corrupt)` if the
+    /// free ranges overlap, are not sorted in descending order
+    /// or not contained in the configured range, or if the used
+    /// count doesn't add up. Adjacent free ranges, as kept by a
+    /// pool with lazy coalescing, are merged.
+    ///
+    /// [`into_parts`]: #method.into_parts
+    pub fn from_parts(
+        range: std::ops::Range<Num>,
+        free: Vec<Range>,
+        used: usize,
+    ) -> Result<Self, IdPoolError> {
+        if range.start > range.end {
+            return Err(IdPoolError::InvalidRange);
+        }
+        let range = Range::from(range);
+        let contained = free
+            .iter()
+            .all(|r| !r.is_empty() && r.start >= range.start && r.end <= range.end);
+        let sorted = free.windows(2).all(|pair| pair[1].end <= pair[0].start);
+        let free_count: Num = free.iter().map(|r| r.len()).sum();
+        if !contained || !sorted || used as Num != range.len() - free_count {
+            return Err(IdPoolError::Corrupt);
+        }
+        let mut pool = Self::try_new_ranged(range.into())?;
+        pool.free.replace(free);
+        pool.compact_ranges_in_place();
+        pool.used = used;
+        Ok(pool)
+    }
+
+    /// Returns all used ids to the pool at once, keeping the
+    /// configured range, blocked and reserved ids intact.
+    ///
+    /// Ids moved over to another pool with [`reclaim_into`]
+    /// count as used, so they are brought back as well.
+    ///
+    /// [`reclaim_into`]: #method.reclaim_into
+    pub fn clear(&mut self) {
+        self.free_from(self.range.start);
+        self.used = 0;
+        self.record_version();
+        self.debug_assert_consistent();
+    }
+
+    /// Computes a CRC-32 checksum over the configured range and
+    /// the free ranges, for detecting corruption of a persisted
+    /// pool. Bounds are hashed as 64-bit little-endian integers,
+    /// so the checksum doesn't depend on the width of `Num`.
+    /// Adjacent free ranges are hashed as one, so the checksum
+    /// doesn't depend on lazy coalescing either.
+    ///
+    /// With the `serde` feature, the `serde_flat::checked` module
+    /// provides a representation that stores and validates it.
+    pub fn checksum(&self) -> u32 {
+        let mut free: Vec<std::ops::Range<Num>> = Vec::with_capacity(self.free.len());
+        for range in self.free_ranges() {
+            match free.last_mut() {
+                Some(last) if last.end == range.start => last.end = range.end,
+                _ => free.push(range),
+            }
+        }
+        let bounds = std::iter::once(self.range.into())
+            .chain(free)
+            .flat_map(|range: std::ops::Range<Num>| [range.start, range.end]);
+        crc32(bounds.flat_map(|bound| (bound as u64).to_le_bytes()))
+    }
+
+    /// Shrinks the capacity of the list of free ranges as much
+    /// as possible.
+    pub fn shrink_to_fit(&mut self) {
+        self.free.shrink_to_fit();
+    }
+
+    /// Shrinks the capacity of the list of free ranges down to
+    /// `max_capacity`, or as close to it as the current number
+    /// of free ranges allows.
+    ///
+    /// The capacity is left untouched if it's already below
+    /// `max_capacity`.
+    pub fn shrink_free_list_to(&mut self, max_capacity: usize) {
+        self.free.shrink_to(max_capacity);
+    }
+
+    /// Cleans up the list of free ranges in a single call, for
+    /// use after a churn-heavy phase.
+    ///
+    /// Empty ranges are dropped first, then adjacent ranges are
+    /// coalesced with [`compact_ranges_in_place`], which matters
+    /// with lazy coalescing enabled, and finally the capacity of
+    /// the list is shrunk to fit. This leaves the pool in the same
+    /// minimal form as [`compacted_clone`], without copying it.
+    ///
+    /// [`compact_ranges_in_place`]: #method.compact_ranges_in_place
+    /// [`compacted_clone`]: #method.compacted_clone
+    pub fn compact(&mut self) {
+        self.prune_empty_ranges();
+        self.compact_ranges_in_place();
+        self.shrink_to_fit();
+        self.debug_assert_consistent();
+    }
+
+    /// Returns a copy of the pool in a canonical minimal form,
+    /// with the free ranges coalesced and the list of free
+    /// ranges shrunk to fit, leaving the original untouched.
+    pub fn compacted_clone(&self) -> IdPool {
+        let mut pool = self.clone();
+        pool.compact_ranges_in_place();
+        pool.shrink_to_fit();
+        pool
+    }
+
+    /// Inserts an id into the list of free ranges, merging
+    /// it with adjacent ranges. Returns the index of the range
+    /// now containing the id or `Err(Num)` if the id is
+    /// already free.
+    fn insert_free(&mut self, id: Num) -> Result<usize, Num> {
+        // ids lie below the exclusive end of the configured range,
+        // so this can't overflow even for a range ending at `Num::MAX`
+        self.insert_free_range(id, id + 1).ok_or(id)
+    }
+
+    /// Inserts a range of ids into the list of free ranges,
+    /// merging it with adjacent ranges. Returns the index of
+    /// the range now containing the inserted one or `None` if
+    /// any of the ids is already free.
+    fn insert_free_range(&mut self, start: Num, end: Num) -> Option<usize> {
+        // ranges are stored in descending order, find the first
+        // range that starts below the end of the inserted range
+        let i = self.free.partition_point(|range| range.start >= end);
+        // the range below the inserted one
+        let below = self.free.get(i).map(|range| range.end);
+        // the range directly above the inserted one
+        let above = i
+            .checked_sub(1)
+            .and_then(|i| self.free.get(i))
+            .map(|range| range.start);
+        let index = match (below, above) {
+            // range overlaps one of the free ranges, can't
+            // return it to the pool
+            (Some(below), _) if below > start => None,
+            // keep the range separate when coalescing lazily
+            _ if self.lazy_coalescing => {
+                self.free.insert(i, Range { start, end });
+                Some(i)
+            }
+            // range bridges the gap between two ranges,
+            // merge the ranges into one
+            (Some(below), Some(above)) if below == start && above == end => {
+                let upper = self.free.remove(i - 1);
+                let lower = self.free.get(i - 1).unwrap();
+                self.free.set(
+                    i - 1,
+                    Range {
+                        start: lower.start,
+                        end: upper.end,
+                    },
+                );
+                Some(i - 1)
+            }
+            // range adjacent to the lower range's end point
+            (Some(below), _) if below == start => {
+                let lower = self.free.get(i).unwrap();
+                self.free.set(
+                    i,
+                    Range {
+                        start: lower.start,
+                        end,
+                    },
+                );
+                Some(i)
+            }
+            // range adjacent to the upper range's start point
+            (_, Some(above)) if above == end => {
+                let upper = self.free.get(i - 1).unwrap();
+                self.free.set(
+                    i - 1,
+                    Range {
+                        start,
+                        end: upper.end,
+                    },
+                );
+                Some(i - 1)
+            }
+            // no adjacent ranges, insert a new range at the
+            // matching point in the list
+            _ => {
+                self.free.insert(i, Range { start, end });
+                Some(i)
+            }
+        };
+        if index.is_some() {
+            self.forget_issued(start..end);
+        }
+        index
+    }
+
+    /// Removes an id from the list of free ranges, splitting
+    /// the range that contains it if necessary. Returns
+    /// `false` if the id is not free.
+    fn remove_free(&mut self, id: Num) -> bool {
+        let i = self.free.partition_point(|range| range.start > id);
+        let mut range = match self.free.get(i) {
+            Some(range) if range.end > id => range,
+            _ => return false,
+        };
+        if range.start == id {
+            range.start += 1;
+            if range.is_empty() {
+                self.free.remove(i);
+            } else {
+                self.free.set(i, range);
+            }
+        } else if range.end - 1 == id {
+            range.end = id;
+            self.free.set(i, range);
+        } else {
+            // split the range, the upper part goes before the
+            // current one to keep the descending order
+            let upper = Range {
+                start: id + 1,
+                end: range.end,
+            };
+            range.end = id;
+            self.free.set(i, range);
+            self.free.insert(i, upper);
+        }
+        true
+    }
+
+    /// Collects the ranges of used ids, in ascending order.
+    fn used_ranges(&self) -> Vec<std::ops::Range<Num>> {
+        let mut ranges = Vec::new();
+        let mut push = |start: Num, end: Num| {
+            // blocked and reserved ids are neither free nor used,
+            // carve them out
+            let mut start = start;
+            for withheld in self.withheld(start, end) {
+                if withheld.start > start {
+                    ranges.push(start..withheld.start);
+                }
+                start = withheld.end;
+            }
+            if end > start {
+                ranges.push(start..end);
+            }
+        };
+        let mut start = self.range.start;
+        for range in self.free.iter().rev() {
+            if range.start > start {
+                push(start, range.start);
+            }
+            start = range.end;
+        }
+        if self.range.end > start {
+            push(start, self.range.end);
+        }
+        ranges
+    }
+
+    /// Checks the internal invariants of the pool in debug builds.
+    fn debug_assert_consistent(&self) {
+        #[cfg(debug_assertions)]
+        self.assert_consistent();
+    }
+
+    /// Rebuilds the free ranges so that all ids from `start` up
+    /// to the end of the configured range are free, except for
+    /// the blocked and reserved ones.
+    fn free_from(&mut self, start: Num) {
+        let start_all = start;
+        let mut free = Vec::new();
+        let mut start = start;
+        for withheld in self.withheld(start, self.range.end) {
+            if withheld.start > start {
+                free.push(Range {
+                    start,
+                    end: withheld.start,
+                });
+            }
+            start = withheld.end;
+        }
+        if self.range.end > start {
+            free.push(Range {
+                start,
+                end: self.range.end,
+            });
+        }
+        free.reverse();
+        self.free.replace(free);
+        self.forget_issued(start_all..self.range.end);
+    }
+
+    /// Counts the free ids below the allocation ceiling.
+    fn free_below_ceiling(&self) -> usize {
+        match self.ceiling {
+            None => self.available() as usize,
+            Some(ceiling) => self
+                .free
+                .iter()
+                .filter(|range| range.start < ceiling)
+                .map(|range| (range.end.min(ceiling) - range.start) as usize)
+                .sum(),
+        }
+    }
+
+    /// Applies the operations in order, ignoring the ones that
+    /// fail.
+    fn apply(&mut self, ops: &[Op]) {
+        for op in ops {
+            match *op {
+                Op::Request => {
+                    self.request_id();
+                }
+                Op::Return(id) => {
+                    let _ = self.return_id(id);
+                }
+            }
+        }
+    }
+
+    /// Checks whether `count` ids can be handed out without
+    /// dipping into the earmarked ones. Every request path goes
+    /// through this before allocating.
+    fn can_allocate(&self, count: usize) -> bool {
+        // counting the free ids takes a pass over the free ranges,
+        // which the common case of no earmarks can skip
+        self.earmarked == 0 || self.available().saturating_sub(self.earmarked) as usize >= count
+    }
+
+    /// Allocates a specific id, returning `false` if the id is
+    /// not free.
+    fn allocate(&mut self, id: Num) -> bool {
+        if !self.remove_free(id) {
+            return false;
+        }
+        self.used += 1;
+        self.total_allocations += 1;
+        self.record_version();
+        true
+    }
+
+    /// Checks whether the given id is currently allocated,
+    /// returning the matching error if it's not.
+    fn check_allocated(&self, id: Num) -> Result<(), IdPoolError> {
+        // reserved ids are not part of the allocatable space at all
+        if id < self.range.start || id >= self.range.end || self.is_reserved(id) {
+            Err(IdPoolError::OutOfRange(id))
+        } else if self.blocked.contains(&id) || self.is_free(id) {
+            Err(IdPoolError::NotAllocated(id))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Collects the runs of blocked and reserved ids lying
+    /// between `start` and `end`, in ascending order.
+    fn withheld(&self, start: Num, end: Num) -> Vec<std::ops::Range<Num>> {
+        let mut withheld: Vec<std::ops::Range<Num>> = self
+            .reserved
+            .iter()
+            .filter(|range| range.end > start && range.start < end)
+            .map(|range| range.start.max(start)..range.end.min(end))
+            .collect();
+        if start < end {
+            withheld.extend(self.blocked.range(start..end).map(|&id| id..id + 1));
+        }
+        withheld.sort_by_key(|range| range.start);
+        withheld
+    }
+
+    /// Gets the lowest id at or above the given one that is
+    /// neither blocked nor reserved.
+    fn skip_withheld(&self, id: Num) -> Num {
+        let mut id = id;
+        loop {
+            if self.blocked.contains(&id) {
+                id += 1;
+            } else if self.is_reserved(id) {
+                let i = self.reserved.partition_point(|range| range.end <= id);
+                id = self.reserved[i].end;
+            } else {
+                return id;
+            }
+        }
+    }
+
+    /// Checks whether a block of `count` ids could be requested
+    /// once the pool is packed, without packing it.
+    fn fits_once_packed(&self, count: Num) -> bool {
+        if !self.can_allocate(count as usize) {
+            return false;
+        }
+        let ceiling = self
+            .ceiling
+            .map_or(self.range.end, |c| c.min(self.range.end));
+        // packing fills the spaces between the withheld ids from
+        // the bottom up, leaving the rest of each space free
+        let mut unplaced = self.used as Num;
+        let mut start = self.range.start;
+        let spaces = self
+            .withheld(self.range.start, self.range.end)
+            .into_iter()
+            .chain(std::iter::once(self.range.end..self.range.end));
+        for withheld in spaces {
+            let placed = unplaced.min(withheld.start - start);
+            unplaced -= placed;
+            let free_start = start + placed;
+            let free_end = withheld.start.min(ceiling);
+            if free_start < free_end && free_end - free_start >= count {
+                return true;
+            }
+            start = withheld.end;
+        }
+        false
+    }
+
+    /// Gets the count of reserved ids.
+    fn reserved_count(&self) -> Num {
+        self.reserved.iter().map(|range| range.len()).sum()
+    }
+
+    /// Removes any empty ranges from the list of free ranges.
+    ///
+    /// Operations trimming or splitting ranges call this as a
+    /// safety pass, so that no empty range is left behind.
+    fn prune_empty_ranges(&mut self) {
+        self.free.retain(|range| !range.is_empty());
+    }
+
+    /// Stops tracking the ids in the given range as handed out,
+    /// once they're free again. This is a no-op in release
+    /// builds.
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    fn forget_issued(&mut self, range: std::ops::Range<Num>) {
+        #[cfg(debug_assertions)]
+        {
+            if ((range.end - range.start) as usize) < self.issued.len() {
+                for id in range {
+                    self.issued.remove(&id);
+                }
+            } else {
+                self.issued.retain(|id| !range.contains(id));
+            }
+        }
+    }
+
+    /// Compacts the free ranges if there are more of them than
+    /// the configured threshold, returning whether it did.
+    fn compact_over_threshold(&mut self) -> bool {
+        match self.coalesce_threshold {
+            Some(threshold) if self.free.len() > threshold => {
+                self.compact_ranges_in_place();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Bumps the version of the pool, recording the used count
+    /// of the new version.
+    fn record_version(&mut self) {
+        self.version += 1;
+        if self.history.len() == VERSION_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back((self.version, self.used));
+        self.check_watermark();
+        #[cfg(feature = "log")]
+        self.check_capacity_warning();
+    }
+
+    /// Logs a warning if the used ids have just reached the
+    /// capacity warning threshold, re-arming the warning once
+    /// usage is back below it.
+    #[cfg(feature = "log")]
+    fn check_capacity_warning(&mut self) {
+        let percent = match self.capacity_warning {
+            Some((percent, _)) => percent,
+            None => return,
+        };
+        let capacity = self.range.len() - self.reserved_count();
+        let used = self.used;
+        let above = used as u128 * 100 >= percent as u128 * capacity as u128;
+        if let Some((percent, armed)) = &mut self.capacity_warning {
+            if above && *armed {
+                *armed = false;
+                log::warn!(
+                    "id pool at {}% capacity ({} of {} ids in use)",
+                    percent,
+                    used,
+                    capacity
+                );
+            } else if !above {
+                *armed = true;
+            }
+        }
+    }
+
+    /// Invokes the low watermark callback if the count of free
+    /// ids has just dropped below the threshold, re-arming the
+    /// watermark once it's back up.
+    fn check_watermark(&mut self) {
+        let threshold = match &self.watermark {
+            Some(watermark) => watermark.threshold,
+            None => return,
+        };
+        let below = self.available() < threshold;
+        if let Some(watermark) = &mut self.watermark {
+            if below && watermark.armed {
+                watermark.armed = false;
+                (watermark.callback.lock().unwrap())();
+            } else if !below {
+                watermark.armed = true;
+            }
+        }
+    }
+
+    /// Appends a pool configured over the range directly above
+    /// this one, undoing [`split_off`].
+    ///
+    /// [`split_off`]: #method.split_off
+    fn append(&mut self, upper: IdPool) {
+        assert_eq!(self.range.end, upper.range.start, "pools not adjacent");
+        // ranges are in descending order, so the upper ones come first
+        let mut free = upper.free.to_vec();
+        if let (Some(lower), Some(bottom)) = (self.free.first(), free.last_mut()) {
+            if lower.end == bottom.start && !self.lazy_coalescing {
+                bottom.start = lower.start;
+                self.free.remove(0);
+            }
+        }
+        free.extend(self.free.iter());
+        self.free.replace(free);
+        self.blocked.extend(upper.blocked);
+        self.reserved.extend(upper.reserved);
+        #[cfg(debug_assertions)]
+        self.issued.extend(upper.issued);
+        self.used += upper.used;
+        self.total_allocations += upper.total_allocations;
+        self.exhaustion_count += upper.exhaustion_count;
+        self.range.end = upper.range.end;
+        self.debug_assert_consistent();
+    }
+
+    /// Records the time elapsed since `started` in the latency
+    /// histogram, if enabled.
+    #[cfg(feature = "hdrhistogram")]
+    fn record_latency(&mut self, started: std::time::Instant) {
+        if let Some(latency) = &mut self.latency {
+            latency.saturating_record(started.elapsed().as_nanos() as u64);
+        }
+    }
+}
+
+/// Number of versions for which the used count is kept.
+const VERSION_HISTORY: usize = 64;
+
+/// Renders ranges as a comma-separated list with inclusive
+/// bounds.
+fn format_ranges(ranges: impl Iterator<Item = std::ops::Range<Num>>) -> String {
+    let ranges: Vec<String> = ranges
+        .map(|range| {
+            if range.end - range.start == 1 {
+                range.start.to_string()
+            } else {
+                format!("{}-{}", range.start, range.end - 1)
+            }
+        })
+        .collect();
+    format!("[{}]", ranges.join(", "))
+}
+
+/// Scrambles the bits of a value, splitmix64 finalizer.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Advances a splitmix64 state, returning the next value. Any
+/// state including 0 gives a well-mixed sequence.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    mix(*state)
+}
+
+/// Computes the CRC-32 (IEEE) checksum of the given bytes.
+fn crc32(bytes: impl Iterator<Item = u8>) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            // reflected polynomial
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request() {
+        let mut pool = IdPool::new();
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Some(2), pool.request_id());
+        assert_eq!(Some(3), pool.request_id());
+    }
+
+    #[test]
+    fn request_return() {
+        let mut pool = IdPool::new();
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Some(2), pool.request_id());
+        assert_eq!(Some(3), pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(1));
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(2));
+        assert_eq!(Some(2), pool.request_id());
+        assert_eq!(Some(4), pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(3));
+        assert_eq!(Ok(()), pool.return_id(4));
+        assert_eq!(Err(5), pool.return_id(5));
+    }
+
+    #[test]
+    fn used_count() {
+        let mut pool = IdPool::new_ranged(1..10);
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Some(2), pool.request_id());
+        assert_eq!(Some(3), pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(1));
+        assert_eq!(2, pool.used_count());
+    }
+
+    #[test]
+    fn return_last() {
+        let mut pool = IdPool::new_ranged(1..10);
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Some(2), pool.request_id());
+        assert_eq!(Some(3), pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(3));
+    }
+
+    #[test]
+    fn block_unblock() {
+        let mut pool = IdPool::new_ranged(1..10);
+        assert_eq!(Ok(()), pool.block_id(2));
+        assert_eq!(Err(2), pool.block_id(2));
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Some(3), pool.request_id());
+        assert_eq!(Err(2), pool.return_id(2));
+        assert_eq!(2, pool.used_count());
+        assert_eq!(Ok(()), pool.unblock_id(2));
+        assert_eq!(Err(2), pool.unblock_id(2));
+        assert_eq!(Some(2), pool.request_id());
+        assert_eq!(Some(4), pool.request_id());
+    }
+
+    #[test]
+    fn gaps() {
+        let mut pool = IdPool::new_ranged(1..10);
+        assert_eq!(0, pool.gaps().count());
+        for _ in 0..6 {
+            pool.request_id();
+        }
+        assert_eq!(Ok(()), pool.return_id(2));
+        assert_eq!(Ok(()), pool.return_id(4));
+        assert_eq!(Ok(()), pool.return_id(5));
+        assert_eq!(vec![2..3, 4..6], pool.gaps().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn request_contiguous() {
+        let mut pool = IdPool::new_ranged(1..10);
+        assert_eq!(Some(1..4), pool.request_contiguous(3));
+        assert_eq!(Some(4), pool.request_id());
+        assert_eq!(Some(5..10), pool.request_contiguous(5));
+        assert_eq!(None, pool.request_contiguous(1));
+        assert_eq!(9, pool.used_count());
+    }
+
+    #[test]
+    fn contiguous_with_pack() {
+        let mut pool = IdPool::new_ranged(1..10);
+        for _ in 0..6 {
+            pool.request_id();
+        }
+        assert_eq!(Ok(()), pool.return_id(2));
+        assert_eq!(Ok(()), pool.return_id(4));
+        // free ids are 2, 4 and 7..10
+        assert_eq!(None, pool.request_contiguous(4));
+        let (block, remap) = pool.try_request_contiguous_with_pack(4).unwrap();
+        assert_eq!(5..9, block);
+        assert_eq!(Some(&2), remap.get(&3));
+        assert_eq!(Some(&3), remap.get(&5));
+        assert_eq!(Some(&4), remap.get(&6));
+        assert_eq!(None, remap.get(&1));
+        assert_eq!(8, pool.used_count());
+        assert_eq!(Some(9), pool.request_id());
+        assert_eq!(None, pool.try_request_contiguous_with_pack(1));
+    }
+
+    #[test]
+    fn contiguous_with_pack_out_of_reach() {
+        let fragmented = || {
+            let mut pool = IdPool::new_ranged(0..10);
+            pool.request_ids(10).unwrap();
+            for id in [1, 3, 5, 7] {
+                pool.return_id(id).unwrap();
+            }
+            pool
+        };
+        // the earmarked ids can't be part of the block
+        let mut pool = fragmented();
+        assert!(pool.try_reserve_capacity(3));
+        assert_eq!(None, pool.try_request_contiguous_with_pack(2));
+        assert_eq!("[1, 3, 5, 7]", pool.free_ranges_string());
+        // packing would leave the free space above the ceiling
+        let mut pool = fragmented();
+        pool.set_allocation_ceiling(Some(4));
+        assert_eq!(None, pool.try_request_contiguous_with_pack(3));
+        assert_eq!("[1, 3, 5, 7]", pool.free_ranges_string());
+        // blocked ids split the packed free space
+        let mut pool = fragmented();
+        pool.block_id(7).unwrap();
+        assert_eq!(None, pool.try_request_contiguous_with_pack(3));
+        assert_eq!("[1, 3, 5]", pool.free_ranges_string());
+        let (block, remap) = pool.try_request_contiguous_with_pack(2).unwrap();
+        assert_eq!(8..10, block);
+        assert_eq!(5, remap.len());
+    }
+
+    #[test]
+    fn raise_start() {
+        let mut pool = IdPool::new_ranged(1..10);
+        assert_eq!(Ok(()), pool.raise_start(4));
+        assert_eq!(Some(4), pool.request_id());
+        assert_eq!(Err(IdPoolError::AlreadyAllocated(4)), pool.raise_start(6));
+        assert_eq!(Err(IdPoolError::InvalidRange), pool.raise_start(2));
+        assert_eq!(Err(IdPoolError::InvalidRange), pool.raise_start(11));
+        assert_eq!(5, pool.available());
+    }
+
+    #[test]
+    fn reclaim_into() {
+        let mut pool = IdPool::new_ranged(1..10);
+        // the other pool starts out with all of its ids held elsewhere
+        let mut other = IdPool::new_ranged(1..10);
+        assert_eq!(Some(1..10), other.request_contiguous(9));
+        assert_eq!(Ok(4), pool.reclaim_into(&mut other, 4));
+        // a fresh pool already considers all of the ids free
+        assert_eq!(Ok(0), pool.reclaim_into(&mut IdPool::new_ranged(1..10), 1));
+        assert_eq!(
+            Err(IdPoolError::RangeMismatch),
+            pool.reclaim_into(&mut IdPool::new_ranged(1..11), 1)
+        );
+        let mut ids = Vec::new();
+        while let Some(id) = pool.request_id() {
+            ids.push(id);
+        }
+        assert_eq!(vec![1, 2, 3, 4, 5], ids);
+        while let Some(id) = other.request_id() {
+            assert!(!ids.contains(&id));
+            ids.push(id);
+        }
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8, 9], ids);
+    }
+
+    #[test]
+    #[allow(clippy::reversed_empty_ranges)]
+    fn inverted_range() {
+        assert_eq!(
+            Some(IdPoolError::InvalidRange),
+            IdPool::try_new_ranged(10..5).err()
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    #[allow(clippy::reversed_empty_ranges)]
+    fn inverted_range_panics() {
+        IdPool::new_ranged(10..5);
+    }
+
+    #[test]
+    fn empty_range() {
+        let mut pool = IdPool::try_new_ranged(5..5).unwrap();
+        assert_eq!(0, pool.available());
+        assert_eq!(None, pool.request_id());
+        assert_eq!(None, pool.request_contiguous(1));
+    }
+
+    #[test]
+    fn compact_ranges_in_place() {
+        let mut pool = IdPool::new_ranged(1..10);
+        pool.free = vec![
+            Range { start: 8, end: 10 },
+            Range { start: 6, end: 8 },
+            Range { start: 6, end: 6 },
+            Range { start: 5, end: 6 },
+            Range { start: 2, end: 4 },
+        ]
+        .into();
+        let capacity = pool.free.capacity();
+        pool.compact_ranges_in_place();
+        assert_eq!(capacity, pool.free.capacity());
+        assert_eq!(2, pool.free.len());
+        assert_eq!(vec![2..4], pool.gaps().collect::<Vec<_>>());
+        assert_eq!(7, pool.available());
+    }
+
+    #[test]
+    fn drain_used_where() {
+        let mut pool = IdPool::new_ranged(1..20);
+        assert_eq!(Some(1..11), pool.request_contiguous(10));
+        assert_eq!(
+            vec![4, 5, 6],
+            pool.drain_used_where(|id| (4..7).contains(&id))
+        );
+        assert_eq!(7, pool.used_count());
+        assert_eq!(Some(4), pool.request_id());
+        assert_eq!(Some(5), pool.request_id());
+        assert_eq!(Some(6), pool.request_id());
+        assert_eq!(Some(11), pool.request_id());
+    }
+
+    #[test]
+    fn parts_round_trip() {
+        let mut pool = IdPool::new_ranged(1..10);
+        pool.request_contiguous(5);
+        assert_eq!(Ok(()), pool.return_id(2));
+        let (range, free, used) = pool.into_parts();
+        assert_eq!(1..10, range);
+        assert_eq!(4, used);
+        let mut pool = IdPool::from_parts(range, free.clone(), used).unwrap();
+        pool.assert_consistent();
+        assert_eq!(Some(2), pool.request_id());
+        assert_eq!(Some(6), pool.request_id());
+        assert_eq!(
+            Some(IdPoolError::Corrupt),
+            IdPool::from_parts(1..10, free.clone(), used + 1).err()
+        );
+        let overlapping = vec![Range::from(3..10), Range::from(2..4)];
+        assert_eq!(
+            Some(IdPoolError::Corrupt),
+            IdPool::from_parts(1..10, overlapping, 1).err()
+        );
+    }
+
+    #[test]
+    fn request_ids() {
+        let mut pool = IdPool::new_ranged(1..10);
+        pool.request_contiguous(4);
+        assert_eq!(Ok(()), pool.return_id(2));
+        assert_eq!(Some(vec![2, 5, 6]), pool.request_ids(3));
+        assert_eq!(6, pool.used_count());
+        assert_eq!(None, pool.request_ids(4));
+        assert_eq!(6, pool.used_count());
+        assert_eq!(Some(vec![7, 8, 9]), pool.request_ids(3));
+        assert_eq!(9, pool.used_count());
+    }
+
+    #[test]
+    fn clear() {
+        let mut pool = IdPool::new_ranged(1..10);
+        assert_eq!(Ok(()), pool.block_id(3));
+        pool.request_contiguous(2);
+        pool.request_id();
+        assert_eq!(Ok(()), pool.return_id(2));
+        pool.clear();
+        assert_eq!(0, pool.used_count());
+        assert_eq!(8, pool.available());
+        assert!(pool.is_blocked(3));
+        assert_eq!(Some(vec![1, 2, 4, 5, 6, 7, 8, 9]), pool.request_ids(8));
+    }
+
+    #[test]
+    fn return_id_reporting() {
+        let mut pool = IdPool::new_ranged(1..10);
+        pool.request_contiguous(6);
+        assert_eq!(Ok(2..3), pool.return_id_reporting(2));
+        assert_eq!(Ok(4..5), pool.return_id_reporting(4));
+        // returning 3 bridges the two ranges
+        assert_eq!(Ok(2..5), pool.return_id_reporting(3));
+        assert_eq!(Ok(6..10), pool.return_id_reporting(6));
+        assert_eq!(Ok(2..10), pool.return_id_reporting(5));
+        assert_eq!(
+            Err(IdPoolError::NotAllocated(3)),
+            pool.return_id_reporting(3)
+        );
+        assert_eq!(1, pool.used_count());
+    }
+
+    #[test]
+    fn sample_boundaries() {
+        let mut pool = IdPool::new_ranged(1..20);
+        pool.request_contiguous(10);
+        assert_eq!(Ok(()), pool.return_id(2));
+        assert_eq!(Ok(()), pool.return_id(5));
+        assert_eq!(Ok(()), pool.return_id(6));
+        let boundaries = pool.sample_boundaries();
+        assert_eq!(vec![2, 5, 11], boundaries);
+        assert_eq!(pool.free_ranges().count(), boundaries.len());
+        for (range, start) in pool.free_ranges().zip(boundaries) {
+            assert_eq!(range.start, start);
+        }
+    }
+
+    #[test]
+    fn grow_to() {
+        let mut pool = IdPool::new_ranged(1..10);
+        assert_eq!(Some(1..10), pool.request_contiguous(9));
+        assert_eq!(None, pool.request_id());
+        assert_eq!(Err(IdPoolError::InvalidRange), pool.grow_to(10));
+        assert_eq!(Ok(()), pool.grow_to(20));
+        assert_eq!(Some(10..20), pool.request_contiguous(10));
+        assert_eq!(None, pool.request_id());
+        assert_eq!(19, pool.used_count());
+    }
+
+    #[test]
+    fn total_allocations() {
+        let mut pool = IdPool::new_ranged(1..10);
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(1));
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(1));
+        assert_eq!(2, pool.total_allocations());
+        pool.request_ids(3);
+        pool.request_contiguous(2);
+        pool.clear();
+        assert_eq!(7, pool.total_allocations());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn total_allocations_serde() {
+        let mut pool = IdPool::new_ranged(1..10);
+        pool.request_ids(3);
+        assert_eq!(Ok(()), pool.return_id(2));
+        let json = serde_json::to_string(&pool).unwrap();
+        let pool: IdPool = serde_json::from_str(&json).unwrap();
+        assert_eq!(3, pool.total_allocations());
+    }
+
+    #[test]
+    fn free_ranges_string() {
+        let mut pool = IdPool::new_ranged(1..201);
+        assert_eq!("[1-200]", pool.free_ranges_string());
+        pool.request_contiguous(200);
+        assert_eq!("[]", pool.free_ranges_string());
+        pool.drain_used_where(|id| id <= 5 || id == 9 || id >= 100);
+        assert_eq!("[1-5, 9, 100-200]", pool.free_ranges_string());
+    }
+
+    #[test]
+    fn ranges_string_round_trip() {
+        let mut pool = IdPool::new_ranged(1..201);
+        pool.request_contiguous(200);
+        pool.drain_used_where(|id| id <= 5 || id == 9 || id >= 100);
+        let s = pool.free_ranges_string();
+        let mut parsed = IdPool::from_ranges_string(1..201, &s).unwrap();
+        assert_eq!(s, parsed.free_ranges_string());
+        assert_eq!(pool.used_count(), parsed.used_count());
+        assert_eq!(Some(vec![1, 2, 3, 4, 5, 9, 100]), parsed.request_ids(7));
+    }
+
+    #[test]
+    fn ranges_string_malformed() {
+        let parse = |s| IdPool::from_ranges_string(1..201, s).err();
+        assert_eq!(Some(IdPoolError::InvalidFormat), parse("1-5, 9"));
+        assert_eq!(Some(IdPoolError::InvalidFormat), parse("[1-5, x]"));
+        assert_eq!(Some(IdPoolError::InvalidFormat), parse("[1-, 9]"));
+        assert_eq!(Some(IdPoolError::InvalidRange), parse("[9, 1-5]"));
+        assert_eq!(Some(IdPoolError::InvalidRange), parse("[1-5, 5-9]"));
+        assert_eq!(Some(IdPoolError::InvalidRange), parse("[100-201]"));
+        assert_eq!(Some(IdPoolError::InvalidRange), parse("[0-5]"));
+        assert_eq!(
+            Some(0),
+            IdPool::from_ranges_string(1..201, "[]")
+                .ok()
+                .map(|p| p.available())
+        );
+    }
+
+    #[test]
+    fn reserve_many() {
+        let mut pool = IdPool::new_ranged(1..10);
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(
+            Err(IdPoolError::AlreadyAllocated(1)),
+            pool.reserve_many(&[3, 5, 1, 7])
+        );
+        assert_eq!(
+            Err(IdPoolError::AlreadyAllocated(3)),
+            pool.reserve_many(&[3, 5, 3])
+        );
+        assert_eq!(
+            Err(IdPoolError::OutOfRange(10)),
+            pool.reserve_many(&[3, 10])
+        );
+        assert_eq!(1, pool.used_count());
+        assert!(pool.is_free(3) && pool.is_free(5) && pool.is_free(7));
+        assert_eq!(Ok(()), pool.reserve_many(&[3, 5, 7]));
+        assert_eq!(4, pool.used_count());
+        assert_eq!(Some(vec![2, 4, 6, 8, 9]), pool.request_ids(5));
+    }
+
+    #[test]
+    fn compacted_clone() {
+        let mut pool = IdPool::new_ranged(1..10);
+        pool.free = Vec::with_capacity(16).into();
+        pool.free.push(Range { start: 6, end: 10 });
+        pool.free.push(Range { start: 4, end: 6 });
+        pool.free.push(Range { start: 1, end: 3 });
+        pool.used = 1;
+        let clone = pool.compacted_clone();
+        assert_eq!(3, pool.free.len());
+        assert_eq!(16, pool.free.capacity());
+        assert_eq!(2, clone.free.len());
+        assert_eq!(2, clone.free.capacity());
+        assert_eq!("[1-2, 4-9]", clone.free_ranges_string());
+        clone.assert_consistent();
+    }
+
+    #[test]
+    fn request_nonzero() {
+        let mut pool = IdPool::new();
+        let id: Option<NonZeroNum> = pool.request_nonzero();
+        assert_eq!(Some(1), id.map(NonZeroNum::get));
+        assert_eq!(
+            std::mem::size_of::<Num>(),
+            std::mem::size_of::<Option<NonZeroNum>>()
+        );
+        assert_eq!(
+            Some(IdPoolError::InvalidRange),
+            IdPool::try_new_nonzero(0..10).err()
+        );
+        let mut pool = IdPool::try_new_nonzero(5..6).unwrap();
+        assert_eq!(Some(5), pool.request_nonzero().map(NonZeroNum::get));
+        assert_eq!(None, pool.request_nonzero());
+    }
+
+    #[test]
+    fn lazy_coalescing() {
+        let mut pool = IdPool::new_ranged(1..10).with_lazy_coalescing(true);
+        pool.request_contiguous(6);
+        assert_eq!(Ok(()), pool.return_id(3));
+        assert_eq!(Ok(()), pool.return_id(2));
+        assert_eq!(Ok(()), pool.return_id(4));
+        assert_eq!(Err(3), pool.return_id(3));
+        assert_eq!(4, pool.free_range_count());
+        assert_eq!("[2, 3, 4, 7-9]", pool.free_ranges_string());
+        pool.compact_ranges_in_place();
+        assert_eq!(2, pool.free_range_count());
+        assert_eq!("[2-4, 7-9]", pool.free_ranges_string());
+        assert_eq!(Some(vec![2, 3, 4, 7]), pool.request_ids(4));
+    }
+
+    #[test]
+    fn used_ranges_string() {
+        let mut pool = IdPool::new_ranged(1..20);
+        assert_eq!("[]", pool.used_ranges_string());
+        pool.request_contiguous(9);
+        pool.drain_used_where(|id| (4..7).contains(&id));
+        assert_eq!("[1-3, 7-9]", pool.used_ranges_string());
+        assert_eq!(Ok(()), pool.block_id(4));
+        pool.request_id();
+        assert_eq!("[1-3, 5, 7-9]", pool.used_ranges_string());
+    }
+
+    #[test]
+    fn nth_free() {
+        let mut pool = IdPool::new_ranged(1..10);
+        pool.request_contiguous(5);
+        assert_eq!(Ok(()), pool.return_id(2));
+        assert_eq!(Some(2), pool.nth_free(0));
+        assert_eq!(Some(6), pool.nth_free(1));
+        assert_eq!(Some(9), pool.nth_free(4));
+        assert_eq!(None, pool.nth_free(5));
+    }
+
+    #[test]
+    fn saturating_return() {
+        let mut pool = IdPool::new_ranged(5..10);
+        pool.request_contiguous(3);
+        let state = pool.free_ranges_string();
+        assert_eq!(Ok(()), pool.saturating_return(4));
+        assert_eq!(Ok(()), pool.saturating_return(10));
+        assert_eq!(state, pool.free_ranges_string());
+        assert_eq!(3, pool.used_count());
+        assert_eq!(Ok(()), pool.saturating_return(5));
+        assert_eq!(Err(5), pool.saturating_return(5));
+        assert_eq!(2, pool.used_count());
+    }
+
+    #[test]
+    fn contiguous_with_pack_budget() {
+        let mut pool = IdPool::new_ranged(1..10);
+        for _ in 0..6 {
+            pool.request_id();
+        }
+        assert_eq!(Ok(()), pool.return_id(2));
+        assert_eq!(Ok(()), pool.return_id(4));
+        // a block that fits without packing ignores the budget
+        assert_eq!(
+            Some(7..10),
+            pool.try_request_contiguous_with_pack_budget(3, 0)
+                .map(|r| r.0)
+        );
+        assert_eq!(Ok(()), pool.return_id(7));
+        assert_eq!(Ok(()), pool.return_id(8));
+        assert_eq!(Ok(()), pool.return_id(9));
+        assert_eq!(None, pool.try_request_contiguous_with_pack_budget(4, 2));
+        assert_eq!("[2, 4, 7-9]", pool.free_ranges_string());
+        let (block, remap) = pool.try_request_contiguous_with_pack_budget(4, 3).unwrap();
+        assert_eq!(5..9, block);
+        assert_eq!(3, remap.len());
+    }
+
+    #[test]
+    fn split_off() {
+        let mut lower = IdPool::new_ranged(1..10);
+        lower.request_contiguous(6);
+        assert_eq!(Ok(()), lower.return_id(4));
+        assert_eq!(Ok(()), lower.return_id(5));
+        let mut upper = lower.split_off(5);
+        assert_eq!("[4]", lower.free_ranges_string());
+        assert_eq!("[5, 7-9]", upper.free_ranges_string());
+        assert_eq!(3, lower.used_count());
+        assert_eq!(1, upper.used_count());
+        // ids just outside of each boundary belong to the other half
+        assert_eq!(Err(0), lower.return_id(0));
+        assert_eq!(Err(5), lower.return_id(5));
+        assert_eq!(Err(4), upper.return_id(4));
+        assert_eq!(Err(10), upper.return_id(10));
+        assert_eq!(
+            Err(IdPoolError::OutOfRange(5)),
+            lower.return_id_reporting(5)
+        );
+        assert_eq!(
+            Err(IdPoolError::OutOfRange(4)),
+            upper.return_id_reporting(4)
+        );
+        assert_eq!(Ok(()), lower.return_id(3));
+        assert_eq!(Ok(()), upper.return_id(6));
+        assert_eq!(Some(3), lower.request_id());
+        assert_eq!(Some(5), upper.request_id());
+    }
+
+    #[test]
+    fn used_chunks() {
+        let mut pool = IdPool::new_ranged(1..20);
+        pool.request_contiguous(12);
+        pool.drain_used_where(|id| id == 3 || id == 8);
+        let chunks: Vec<Vec<Num>> = pool.used_chunks(3).collect();
+        assert_eq!(
+            vec![vec![1, 2, 4], vec![5, 6, 7], vec![9, 10, 11], vec![12]],
+            chunks
+        );
+        assert_eq!(0, IdPool::new().used_chunks(3).count());
+    }
+
+    #[test]
+    fn swap_id() {
+        let mut pool = IdPool::new_ranged(1..4);
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Ok(2), pool.swap_id(1));
+        assert_eq!(Ok(1), pool.swap_id(2));
+        assert_eq!(Err(IdPoolError::NotAllocated(2)), pool.swap_id(2));
+        assert_eq!(Err(IdPoolError::OutOfRange(4)), pool.swap_id(4));
+        assert_eq!(Some(vec![2, 3]), pool.request_ids(2));
+        assert_eq!(
+            Err(IdPoolError::Exhausted {
+                capacity: 3,
+                used: 3
+            }),
+            pool.swap_id(3)
+        );
+        assert_eq!(3, pool.used_count());
+        assert_eq!(0, pool.available());
+    }
+
+    #[test]
+    fn exhaustion_count() {
+        let mut pool = IdPool::new_ranged(1..3);
         assert_eq!(Some(1), pool.request_id());
         assert_eq!(Some(2), pool.request_id());
-        assert_eq!(Some(3), pool.request_id());
+        assert_eq!(0, pool.exhaustion_count());
+        for _ in 0..3 {
+            assert_eq!(None, pool.request_id());
+        }
+        assert_eq!(3, pool.exhaustion_count());
+        assert_eq!(Ok(()), pool.return_id(1));
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(3, pool.exhaustion_count());
+    }
+
+    #[test]
+    fn with_reserved_ranges() {
+        let mut pool = IdPool::with_reserved_ranges(0..10, &[6..8, 1..3]).unwrap();
+        assert_eq!(6, pool.available());
+        assert!(pool.is_reserved(1));
+        assert!(!pool.is_reserved(3));
+        assert_eq!(Some(vec![0, 3, 4, 5, 8, 9]), pool.request_ids(6));
+        assert_eq!(None, pool.request_id());
+        assert_eq!(Err(2), pool.return_id(2));
+        assert_eq!(Err(6), pool.return_id(6));
+        assert_eq!(Err(IdPoolError::OutOfRange(7)), pool.return_id_reporting(7));
+        assert_eq!("[0, 3-5, 8-9]", pool.used_ranges_string());
+        pool.clear();
+        assert_eq!("[0, 3-5, 8-9]", pool.free_ranges_string());
+        pool.assert_consistent();
+
+        assert_eq!(
+            Err(IdPoolError::InvalidRange),
+            IdPool::with_reserved_ranges(0..10, &[1..5, 4..6]).map(|_| ())
+        );
+        assert_eq!(
+            Err(IdPoolError::InvalidRange),
+            IdPool::with_reserved_ranges(0..10, &[2..3, 8..11]).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn no_empty_ranges() {
+        let mut pool = IdPool::new_ranged(0..10);
+        // trim and split right at the range boundaries
+        assert_eq!(Ok(()), pool.raise_start(3));
+        let mut upper = pool.split_off(3);
+        assert_eq!(0, pool.free_range_count());
+        assert_eq!(Some(vec![3, 4]), upper.request_ids(2));
+        let mut lower = IdPool::new_ranged(3..10);
+        assert_eq!(Some(3..10), lower.request_contiguous(7));
+        assert_eq!(Ok(5), upper.reclaim_into(&mut lower, 5));
+        assert_eq!(0, upper.free_range_count());
+        let top = upper.split_off(10);
+        for pool in [&pool, &upper, &lower, &top].iter() {
+            assert!(pool.free_ranges().all(|range| !range.is_empty()));
+            pool.assert_consistent();
+        }
+    }
+
+    #[test]
+    fn request_id_with_remaining() {
+        let mut pool = IdPool::new_ranged(0..3);
+        assert_eq!(Some((0, 2)), pool.request_id_with_remaining());
+        assert_eq!(Some((1, 1)), pool.request_id_with_remaining());
+        assert_eq!(Ok(()), pool.return_id(0));
+        assert_eq!(Some((0, 1)), pool.request_id_with_remaining());
+        assert_eq!(Some((2, 0)), pool.request_id_with_remaining());
+        assert_eq!(None, pool.request_id_with_remaining());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "handed out twice")]
+    fn duplicate_hand_out() {
+        let mut pool = IdPool::from_parts(0..4, vec![Range { start: 0, end: 4 }], 0).unwrap();
+        assert_eq!(Some(0), pool.request_id());
+        // corrupt the pool so that the id is considered free again
+        pool.free = vec![Range { start: 0, end: 4 }].into();
+        pool.request_id();
+    }
+
+    #[test]
+    fn coalesce_threshold() {
+        let mut pool = IdPool::new_ranged(0..10)
+            .with_lazy_coalescing(true)
+            .with_coalesce_threshold(2);
+        pool.request_ids(10);
+        assert_eq!(Ok(()), pool.return_id(0));
+        assert_eq!(Ok(()), pool.return_id(1));
+        assert_eq!(2, pool.free_range_count());
+        // going over the threshold merges the adjacent ranges
+        assert_eq!(Ok(()), pool.return_id(2));
+        assert_eq!(1, pool.free_range_count());
+        assert_eq!(Ok(()), pool.return_id(3));
+        assert_eq!(Ok(()), pool.return_id(5));
+        assert_eq!(2, pool.free_range_count());
+        assert_eq!(Ok(0..6), pool.return_id_reporting(4));
+        assert_eq!(1, pool.free_range_count());
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn request_block_or_grow() {
+        let mut pool = IdPool::new_ranged(0..10);
+        assert_eq!(Some(0..4), pool.request_block_or_grow(4, 8));
+        assert_eq!(Ok(()), pool.return_id(2));
+        // the tail 4..10 is extended by a single increment
+        assert_eq!(Some(4..14), pool.request_block_or_grow(10, 8));
+        assert_eq!(18, pool.available() + pool.used_count() as Num);
+        assert_eq!(Some(14..18), pool.request_block_or_grow(4, 0));
+        // nothing free at the top, grow exactly as much as needed
+        assert_eq!(Some(18..21), pool.request_block_or_grow(3, 0));
+        assert_eq!("[2]", pool.free_ranges_string());
+        assert_eq!(None, pool.request_block_or_grow(Num::MAX, 1));
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn request_block_or_grow_withheld_tail() {
+        // the tail fits the block, but earmarks hold most of it
+        let mut pool = IdPool::new_ranged(0..10);
+        assert!(pool.try_reserve_capacity(8));
+        assert_eq!(Some(0..3), pool.request_block_or_grow(3, 1));
+        assert_eq!("[3-10]", pool.free_ranges_string());
+        // the ceiling keeps the tail out of reach
+        let mut pool = IdPool::new_ranged(0..10);
+        pool.set_allocation_ceiling(Some(2));
+        assert_eq!(None, pool.request_block_or_grow(3, 1));
+        assert_eq!("[0-9]", pool.free_ranges_string());
+    }
+
+    #[test]
+    fn request_block_or_grow_ceiling() {
+        let mut pool = IdPool::new_ranged(0..10);
+        pool.request_ids(10).unwrap();
+        // the grown ids would partly lie above the ceiling
+        pool.set_allocation_ceiling(Some(12));
+        assert_eq!(None, pool.request_block_or_grow(3, 8));
+        assert_eq!(0, pool.available());
+        pool.set_allocation_ceiling(Some(13));
+        assert_eq!(Some(10..13), pool.request_block_or_grow(3, 8));
+        assert_eq!("[13-17]", pool.free_ranges_string());
+    }
+
+    #[test]
+    fn request_returns_lowest_available() {
+        let mut rng = testing::XorShift::new(0x2545_f491_4f6c_dd1d);
+        let mut next = move || rng.next_u64();
+        for _ in 0..2000 {
+            let mut pool = IdPool::new_ranged(0..64);
+            let mut used = Vec::new();
+            for _ in 0..64 {
+                if next() % 3 == 0 && !used.is_empty() {
+                    let i = (next() % used.len() as u64) as usize;
+                    assert_eq!(Ok(()), pool.return_id(used.swap_remove(i)));
+                } else {
+                    let expected = pool.lowest_available();
+                    let id = pool.request_id();
+                    assert_eq!(expected, id);
+                    // the lowest free id is below any other free id
+                    if let Some(id) = id {
+                        assert!(pool.free_ranges().all(|range| range.start > id));
+                        used.push(id);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn return_at_num_max() {
+        let mut pool = IdPool::new_ranged(Num::MAX - 2..Num::MAX);
+        assert_eq!(Some(Num::MAX - 2), pool.request_id());
+        assert_eq!(Some(Num::MAX - 1), pool.request_id());
+        assert_eq!(None, pool.request_id());
+        // returning the top id extends nothing past `Num::MAX`
+        assert_eq!(Ok(()), pool.return_id(Num::MAX - 1));
+        assert_eq!(Ok(()), pool.return_id(Num::MAX - 2));
+        assert_eq!(Err(Num::MAX), pool.return_id(Num::MAX));
+        assert_eq!(
+            vec![Num::MAX - 2..Num::MAX],
+            pool.free_ranges().collect::<Vec<_>>()
+        );
+        assert_eq!(Some(Num::MAX - 2), pool.request_id());
+        assert_eq!(
+            Ok(Num::MAX - 2..Num::MAX),
+            pool.return_id_reporting(Num::MAX - 2)
+        );
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn shrink_free_list_to() {
+        let mut pool = IdPool::new_ranged(0..100);
+        pool.request_ids(100);
+        for id in (0..100).step_by(2) {
+            assert_eq!(Ok(()), pool.return_id(id));
+        }
+        pool.drain_used_where(|_| true);
+        assert_eq!(1, pool.free_range_count());
+        assert!(pool.free.capacity() >= 50);
+        pool.shrink_free_list_to(8);
+        assert!(pool.free.capacity() >= 8 && pool.free.capacity() < 50);
+        // the capacity never drops below the length
+        pool.shrink_free_list_to(0);
+        assert!(pool.free.capacity() >= 1);
+        assert_eq!(100, pool.available());
+    }
+
+    #[test]
+    fn request_id_if() {
+        let mut pool = IdPool::new_ranged(0..2);
+        let mut offered = Vec::new();
+        assert_eq!(
+            None,
+            pool.request_id_if(|id| {
+                offered.push(id);
+                false
+            })
+        );
+        assert_eq!(vec![0], offered);
+        assert_eq!(0, pool.used_count());
+        assert_eq!(0, pool.total_allocations());
+        assert_eq!(Some(0), pool.request_id_if(|id| id == 0));
+        assert_eq!(Some(1), pool.request_id_if(|_| true));
+        assert_eq!(None, pool.request_id_if(|_| true));
+        assert_eq!(2, pool.used_count());
+    }
+
+    #[test]
+    fn bulk_return() {
+        let mut pool = IdPool::new_ranged(0..10);
+        pool.request_ids(8);
+        assert_eq!(Ok(()), pool.block_id(9));
+        let report = pool.bulk_return(&[1, 3, 2, 3, 9, 8, 12, 6]);
+        assert_eq!(
+            BulkReturnReport {
+                returned: 4,
+                already_free: 2,
+                out_of_range: 1,
+                withheld: 1,
+                merges: 2,
+            },
+            report
+        );
+        assert_eq!("[1-3, 6, 8]", pool.free_ranges_string());
+        assert_eq!(4, pool.used_count());
+    }
+
+    #[test]
+    fn try_request_id() {
+        let mut pool = IdPool::new_ranged(0..4);
+        assert_eq!(Ok(()), pool.block_id(3));
+        assert_eq!(Ok(0), pool.try_request_id());
+        assert_eq!(Some(vec![1, 2]), pool.request_ids(2));
+        let err = pool.try_request_id().unwrap_err();
+        assert_eq!(
+            IdPoolError::Exhausted {
+                capacity: 4,
+                used: 3
+            },
+            err
+        );
+        assert_eq!("no free ids left (3 of 4 ids in use)", err.to_string());
+        assert_eq!(1, pool.exhaustion_count());
+    }
+
+    #[test]
+    fn allocation_ceiling() {
+        let mut pool = IdPool::new_ranged(0..10);
+        pool.set_allocation_ceiling(Some(3));
+        let ids: Vec<Num> = (0..4).filter_map(|_| pool.request_id()).collect();
+        assert_eq!(vec![0, 1, 2], ids);
+        assert_eq!(None, pool.lowest_available());
+        assert_eq!(7, pool.available());
+        assert_eq!(Ok(()), pool.return_id(1));
+        assert_eq!(Some(1), pool.request_id());
+        pool.set_allocation_ceiling(Some(5));
+        let ids: Vec<Num> = (0..3).filter_map(|_| pool.request_id()).collect();
+        assert_eq!(vec![3, 4], ids);
+        pool.set_allocation_ceiling(None);
+        assert_eq!(Some(5), pool.request_id());
+        assert_eq!(None, pool.allocation_ceiling());
+    }
+
+    #[test]
+    fn allocation_ceiling_batches() {
+        let mut pool = IdPool::new_ranged(0..20);
+        pool.set_allocation_ceiling(Some(3));
+        assert_eq!(None, pool.request_ids(6));
+        assert_eq!(None, pool.request_contiguous(4));
+        assert_eq!(Some(0..2), pool.request_contiguous(2));
+        assert_eq!(None, pool.request_contiguous(2));
+        assert_eq!(Some(vec![2]), pool.request_ids(1));
+        assert_eq!(None, pool.request_strided(1, 1));
+        assert_eq!(None, pool.request_ids_within_span(1, 20));
+        pool.set_allocation_ceiling(Some(10));
+        assert_eq!(None, pool.request_strided(3, 4));
+        assert_eq!(Some(vec![3, 7]), pool.request_strided(2, 4));
+        assert_eq!(None, pool.request_ids_within_span(6, 20));
+        assert_eq!(Some(vec![4, 5, 6]), pool.request_ids_within_span(3, 3));
+        assert_eq!(Some(vec![8, 9]), pool.request_ids(2));
+        assert_eq!(None, pool.request_ids(1));
+        assert_eq!(10, pool.available());
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn simulate() {
+        let mut pool = IdPool::new_ranged(0..10);
+        pool.request_ids(4);
+        let ops = [
+            Op::Request,
+            Op::Return(1),
+            Op::Return(1),
+            Op::Return(3),
+            Op::Request,
+            Op::Return(20),
+            Op::Return(0),
+        ];
+        let before = pool.stats();
+        let simulated = pool.simulate(&ops);
+        assert_eq!(before, pool.stats());
+        assert_eq!(Some(4), pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(1));
+        assert_eq!(Ok(()), pool.return_id(3));
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(0));
+        assert_eq!(
+            PoolStats {
+                used: 3,
+                available: 7,
+                free_ranges: 3,
+                largest_free_range: 5,
+            },
+            pool.stats()
+        );
+        assert_eq!(pool.stats(), simulated);
+    }
+
+    #[test]
+    fn request_for() {
+        let mut pool = IdPool::new_ranged(0..7);
+        for _ in 0..3 {
+            let even = pool.request_for(0, 2).unwrap();
+            let odd = pool.request_for(1, 2).unwrap();
+            assert_eq!(0, even % 2);
+            assert_eq!(1, odd % 2);
+        }
+        // odd ids are exhausted, fall back to the remaining even one
+        assert_eq!(Some(6), pool.request_for(1, 2));
+        assert_eq!(None, pool.request_for(0, 2));
         assert_eq!(Ok(()), pool.return_id(3));
+        assert_eq!(Some(3), pool.request_for(0, 2));
+    }
+
+    #[test]
+    fn request_for_many_consumers() {
+        let mut pool = IdPool::new_ranged(0..10);
+        let count = (Num::MAX as usize).saturating_add(1);
+        // only the consumer's own index is congruent to it
+        assert_eq!(Some(5), pool.request_for(5, count));
+        assert_eq!(Some(0), pool.request_for(5, count));
+        assert_eq!(Some(1), pool.request_for(count - 1, count));
+        assert_eq!(Some(9), pool.request_for(9, usize::MAX));
+        assert_eq!(Some(2), pool.request_for(usize::MAX - 1, usize::MAX));
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn compact() {
+        let mut pool = IdPool::new_ranged(0..20).with_lazy_coalescing(true);
+        pool.request_ids(20);
+        for id in (0..8).chain(12..16) {
+            assert_eq!(Ok(()), pool.return_id(id));
+        }
+        assert_eq!(12, pool.free_range_count());
+        // leave an empty range behind by hand
+        pool.free.insert(4, Range { start: 11, end: 11 });
+        pool.compact();
+        assert_eq!("[0-7, 12-15]", pool.free_ranges_string());
+        assert_eq!(2, pool.free.capacity());
+        assert_eq!(8, pool.used_count());
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn from_free_list() {
+        let mut pool = IdPool::from_free_list(&[10, 4, 3, 5, 11, 4]).unwrap();
+        assert_eq!(5, pool.available());
+        assert_eq!("[3-5, 10-11]", pool.free_ranges_string());
+        let ids: Vec<Num> = (0..6).filter_map(|_| pool.request_id()).collect();
+        assert_eq!(vec![3, 4, 5, 10, 11], ids);
+        assert_eq!(Err(7), pool.return_id(7));
+        assert_eq!(Err(2), pool.return_id(2));
+        assert_eq!(Ok(()), pool.return_id(10));
+        assert_eq!(Some(10), pool.request_id());
+        assert_eq!(
+            Err(IdPoolError::OutOfRange(Num::MAX)),
+            IdPool::from_free_list(&[Num::MAX]).map(|_| ())
+        );
+        assert_eq!(0, IdPool::from_free_list(&[]).unwrap().available());
+    }
+
+    #[test]
+    fn used_at_version() {
+        let mut pool = IdPool::new_ranged(0..200);
+        assert_eq!(Some(0), pool.used_at_version(0));
+        pool.request_id();
+        let first = pool.version();
+        assert_eq!(Some(vec![1, 2, 3]), pool.request_ids(3));
+        assert_eq!(Ok(()), pool.return_id(2));
+        let second = pool.version();
+        assert_eq!(first + 2, second);
+        assert_eq!(Some(1), pool.used_at_version(first));
+        assert_eq!(Some(4), pool.used_at_version(first + 1));
+        assert_eq!(Some(3), pool.used_at_version(second));
+        assert_eq!(None, pool.used_at_version(second + 1));
+        // only the most recent versions are kept
+        for _ in 0..100 {
+            pool.request_id();
+        }
+        assert_eq!(None, pool.used_at_version(second));
+        assert_eq!(Some(103), pool.used_at_version(pool.version()));
+        assert_eq!(Some(102), pool.used_at_version(pool.version() - 1));
+    }
+
+    #[test]
+    fn request_pinned() {
+        let mut pool = IdPool::new_ranged(0..6);
+        assert_eq!(Some(5), pool.request_pinned());
+        assert_eq!(Some(0), pool.request_id());
+        assert_eq!(Some(4), pool.request_pinned());
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(5));
+        assert_eq!(Some(5), pool.request_pinned());
+        assert_eq!(Some(2), pool.request_id());
+        assert_eq!(Some(3), pool.request_pinned());
+        assert_eq!(None, pool.request_pinned());
+        assert_eq!(None, pool.request_id());
+        assert_eq!(6, pool.used_count());
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn merge() {
+        let mut pool = IdPool::new_ranged(0..10);
+        pool.request_ids(10);
+        // ids are handed out by `pool` but managed by `other`
+        let mut other = IdPool::new_ranged(0..10);
+        other.request_ids(10);
+        assert_eq!(Ok(()), other.return_id(3));
+        assert_eq!(Ok(()), other.return_id(4));
+        assert!(!pool.has_same_range(&IdPool::new_ranged(0..5)));
+        assert_eq!(
+            Err(IdPoolError::RangeMismatch),
+            pool.merge(IdPool::new_ranged(0..5))
+        );
+        assert!(pool.has_same_range(&other));
+        assert_eq!(Ok(()), pool.merge(other.clone()));
+        assert_eq!("[3-4]", pool.free_ranges_string());
+        assert_eq!(8, pool.used_count());
+        // ids free in both pools can't be merged
+        assert_eq!(Err(IdPoolError::NotAllocated(3)), pool.merge(other));
+        assert_eq!(8, pool.used_count());
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn rollback_allocation() {
+        let mut pool = IdPool::new_ranged(0..20);
+        assert_eq!(Some(vec![0, 1]), pool.request_ids(2));
+        assert_eq!(Ok(()), pool.return_id(1));
+        let (ids, token) = pool.request_ids_with_token(4).unwrap();
+        assert_eq!(vec![1, 2, 3, 4], ids);
+        assert_eq!(1..5, token.ranges()[0]);
+        assert_eq!(1, token.ranges().len());
+        // unrelated operations in the meantime
+        assert_eq!(Ok(()), pool.return_id(0));
+        assert_eq!(Some(vec![0, 5, 6]), pool.request_ids(3));
+        assert_eq!(Ok(()), pool.return_id(5));
+        assert_eq!(Ok(()), pool.rollback_allocation(token.clone()));
+        assert_eq!("[0, 6]", pool.used_ranges_string());
+        assert_eq!("[1-5, 7-19]", pool.free_ranges_string());
+        // rolling back twice is rejected
+        assert_eq!(
+            Err(IdPoolError::NotAllocated(1)),
+            pool.rollback_allocation(token)
+        );
+        assert_eq!(2, pool.used_count());
+    }
+
+    #[test]
+    fn earmarks() {
+        let mut pool = IdPool::new_ranged(0..10);
+        assert!(pool.try_reserve_capacity(6));
+        assert!(!pool.try_reserve_capacity(5));
+        assert_eq!(None, pool.request_ids(5));
+        assert_eq!(Some(vec![0, 1, 2]), pool.request_ids(3));
+        assert_eq!(Some(3), pool.request_id());
+        assert_eq!(None, pool.request_id());
+        assert_eq!(6, pool.available());
+        pool.release_earmark(6);
+        assert_eq!(0, pool.earmarked());
+        assert_eq!(Some(vec![4, 5, 6, 7, 8, 9]), pool.request_ids(6));
+    }
+
+    #[test]
+    fn earmarks_all_request_paths() {
+        let mut pool = IdPool::new_ranged(0..10);
+        assert!(pool.try_reserve_capacity(8));
+        assert_eq!(None, pool.request_contiguous(5));
+        assert_eq!(Some(0..2), pool.request_contiguous(2));
+        assert_eq!(None, pool.request_pinned());
+        assert_eq!(None, pool.request_for(1, 2));
+        assert_eq!(None, pool.request_strided(1, 2));
+        assert_eq!(None, pool.request_ids_within_span(1, 4));
+        assert_eq!(
+            Err(IdPoolError::Exhausted {
+                capacity: 10,
+                used: 2
+            }),
+            pool.reserve_many(&[5])
+        );
+        pool.release_earmark(1);
+        assert_eq!(Some(9), pool.request_pinned());
+        assert_eq!(None, pool.request_pinned());
+        assert_eq!(
+            Err(IdPoolError::AlreadyAllocated(9)),
+            pool.reserve_many(&[9])
+        );
+        // growing makes room for the block on top of the earmark
+        assert_eq!(Some(2..5), pool.request_block_or_grow(3, 1));
+        assert_eq!(7, pool.available());
+        assert_eq!(None, pool.request_id());
+        pool.assert_consistent();
+    }
+
+    #[test]
+    #[cfg(not(feature = "u16"))]
+    fn new_unicode_scalar() {
+        let mut pool = IdPool::new_unicode_scalar();
+        assert_eq!(0x11_0000 - 0x800, pool.available());
+        assert_eq!(Some(0..0xD800), pool.request_contiguous(0xD800));
+        assert_eq!(Some(0xE000), pool.request_id());
+        let ids = pool.request_ids(pool.available() as usize).unwrap();
+        assert_eq!(Some(&0x10_FFFF), ids.last());
+        for id in std::iter::once(0xE000).chain(ids) {
+            assert!(!(0xD800..0xE000).contains(&id));
+            assert!(std::char::from_u32(id as u32).is_some());
+        }
+        assert_eq!(None, pool.request_id());
+        assert_eq!(Err(0xD800), pool.return_id(0xD800));
+    }
+
+    #[test]
+    fn low_watermark() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut pool = IdPool::new_ranged(0..10);
+        pool.set_low_watermark(3, {
+            let calls = calls.clone();
+            move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        assert_eq!(Some(vec![0, 1, 2, 3, 4, 5, 6]), pool.request_ids(7));
+        assert_eq!(0, calls.load(Ordering::SeqCst));
+        assert_eq!(Some(7), pool.request_id());
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+        // staying below the watermark doesn't fire again
+        assert_eq!(Some(8), pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(8));
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+        // getting back up re-arms the watermark
+        assert_eq!(Ok(()), pool.return_id(7));
+        assert_eq!(Some(7), pool.request_id());
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+        pool.clear_low_watermark();
+        pool.request_ids(2);
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn request_allocated() {
+        let mut pool = IdPool::new_ranged(5..10);
+        let id = pool.request_allocated().unwrap();
+        assert_eq!(5, *id);
+        assert_eq!(6, *id + 1);
+        assert!(pool.request_allocated().unwrap() > id);
+        assert_eq!(Ok(()), pool.return_id(id.into_raw()));
+        assert_eq!(Some(5), pool.request_allocated().map(AllocatedId::into_raw));
+    }
+
+    #[test]
+    fn full_space_extremes() {
+        let mut pool = IdPool::new_ranged(0..Num::MAX);
+        assert_eq!(Num::MAX, pool.available());
+        assert_eq!(Some(0), pool.request_id());
+        assert_eq!(Some(Num::MAX - 1), pool.request_pinned());
+        assert_eq!(Ok(()), pool.reserve_many(&[Num::MAX - 2]));
+        assert_eq!(Num::MAX - 3, pool.available());
+        assert_eq!(Ok(()), pool.return_id(Num::MAX - 1));
+        assert_eq!(Ok(()), pool.return_id(0));
+        assert_eq!(Err(Num::MAX), pool.return_id(Num::MAX));
+        assert_eq!(Ok(()), pool.return_id(Num::MAX - 2));
+        assert_eq!(vec![0..Num::MAX], pool.free_ranges().collect::<Vec<_>>());
+        pool.assert_consistent();
+    }
+
+    #[test]
+    #[cfg(feature = "u16")]
+    fn full_u16_space() {
+        let mut pool = IdPool::new_ranged(0..u16::MAX);
+        let mut count = 0;
+        while let Some(id) = pool.request_id() {
+            assert_eq!(count, id);
+            count += 1;
+        }
+        assert_eq!(u16::MAX, count);
+        assert_eq!(Ok(()), pool.return_id(u16::MAX - 1));
+        assert_eq!(Ok(()), pool.return_id(0));
+        assert_eq!("[0, 65534]", pool.free_ranges_string());
+        assert_eq!(Some(0), pool.request_id());
+        assert_eq!(Some(u16::MAX - 1), pool.request_id());
+        assert_eq!(None, pool.request_id());
+        // growing past the end of the space overflows
+        assert_eq!(None, pool.request_block_or_grow(1, 1));
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn request_id_with_source() {
+        let mut pool = IdPool::new_ranged(0..10);
+        pool.request_ids(5);
+        assert_eq!(Ok(()), pool.return_id(1));
+        assert_eq!(Ok(()), pool.return_id(2));
+        let before: Vec<_> = pool.free_ranges().collect();
+        let (id, index) = pool.request_id_with_source().unwrap();
+        assert_eq!((1, 0), (id, index));
+        let after: Vec<_> = pool.free_ranges().collect();
+        assert_eq!(before[index].start + 1, after[index].start);
+        assert_eq!(Some((2, 0)), pool.request_id_with_source());
+        // the emptied range is gone, the next one comes first
+        assert_eq!(Some((5, 0)), pool.request_id_with_source());
+        assert_eq!(vec![6..10], pool.free_ranges().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn growth_policy() {
+        let mut pool = IdPool::new_ranged(0..1).with_growth_policy(GrowthPolicy::Double);
+        let mut capacities = vec![];
+        for id in 0..64 {
+            assert_eq!(Some(id), pool.request_id_or_grow());
+            let capacity = pool.range.len();
+            if capacities.last() != Some(&capacity) {
+                capacities.push(capacity);
+            }
+        }
+        assert_eq!(vec![1, 2, 4, 8, 16, 32, 64], capacities);
+
+        let mut pool = IdPool::new_ranged(0..1).with_growth_policy(GrowthPolicy::Fixed(10));
+        for _ in 0..25 {
+            pool.request_id_or_grow();
+        }
+        assert_eq!(31, pool.range.len());
+
+        let mut pool = IdPool::new_ranged(0..2)
+            .with_growth_policy(GrowthPolicy::Custom(|capacity| capacity / 2));
+        for _ in 0..4 {
+            pool.request_id_or_grow();
+        }
+        assert_eq!(4, pool.range.len());
+        assert_eq!(None, IdPool::new_ranged(0..0).request_id_or_grow());
+    }
+
+    #[test]
+    fn growth_past_withheld_ids() {
+        let mut pool = IdPool::new_ranged(0..4).with_growth_policy(GrowthPolicy::Fixed(4));
+        assert_eq!(Ok(()), pool.block_id(3));
+        for id in 0..3 {
+            assert_eq!(Some(id), pool.request_id_or_grow());
+        }
+        assert_eq!(Some(4), pool.request_id_or_grow());
+        assert_eq!(8, pool.range.len());
+
+        // the only free ids left are earmarked
+        let mut pool = IdPool::new_ranged(0..2).with_growth_policy(GrowthPolicy::Fixed(2));
+        assert!(pool.try_reserve_capacity(2));
+        assert_eq!(Some(0), pool.request_id_or_grow());
+        assert_eq!(4, pool.range.len());
+
+        // growing doesn't help below the ceiling
+        let mut pool = IdPool::new_ranged(0..4).with_growth_policy(GrowthPolicy::Fixed(4));
+        pool.set_allocation_ceiling(Some(4));
+        assert_eq!(Some(vec![0, 1, 2, 3]), pool.request_ids(4));
+        assert_eq!(None, pool.request_id_or_grow());
+        assert_eq!(4, pool.range.len());
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn including_zero() {
+        let mut pool = IdPool::new_including_zero();
+        assert_eq!(Some(0), pool.request_id());
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(0));
+        assert_eq!(Err(0), pool.return_id(0));
+        assert_eq!(
+            vec![0..1, 2..Num::MAX],
+            pool.free_ranges().collect::<Vec<_>>()
+        );
+        assert_eq!(Ok(()), pool.return_id(1));
+        assert_eq!(1, pool.free_range_count());
+        assert_eq!(Some(0), pool.request_id());
+        pool.assert_consistent();
+    }
+
+    #[test]
+    #[cfg(feature = "log")]
+    fn capacity_warning() {
+        use std::sync::Mutex;
+
+        static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        struct Capture;
+
+        impl log::Log for Capture {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                if record.level() == log::Level::Warn {
+                    WARNINGS.lock().unwrap().push(record.args().to_string());
+                }
+            }
+
+            fn flush(&self) {}
+        }
+
+        log::set_logger(&Capture).unwrap();
+        log::set_max_level(log::LevelFilter::Warn);
+        let mut pool = IdPool::new_ranged(0..10).with_capacity_warning_threshold(80);
+        pool.request_ids(7).unwrap();
+        assert!(WARNINGS.lock().unwrap().is_empty());
+        pool.request_id();
+        pool.request_id();
+        assert_eq!(
+            vec!["id pool at 80% capacity (8 of 10 ids in use)"],
+            *WARNINGS.lock().unwrap()
+        );
+        // dropping below the threshold re-arms the warning
+        pool.return_id(0).unwrap();
+        pool.return_id(1).unwrap();
+        pool.request_id();
+        assert_eq!(2, WARNINGS.lock().unwrap().len());
+    }
+
+    #[test]
+    fn shrink_start_to_lowest_used() {
+        let mut pool = IdPool::new_ranged(0..10);
+        let mut unused = IdPool::new_ranged(0..10);
+        unused.shrink_start_to_lowest_used();
+        assert_eq!(0, unused.range.start);
+        pool.request_ids(6).unwrap();
+        for id in 0..4 {
+            pool.return_id(id).unwrap();
+        }
+        pool.block_id(1).unwrap();
+        pool.shrink_start_to_lowest_used();
+        assert_eq!(4, pool.range.start);
+        assert!(!pool.is_blocked(1));
+        assert_eq!(2, pool.used_count());
+        assert_eq!(4, pool.available());
+        assert_eq!(Some(6), pool.request_id());
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn new_from_ranges() {
+        let mut pool = IdPool::new_from_ranges(&[0..10, 100..110]).unwrap();
+        assert_eq!(20, pool.available());
+        let ids = pool.request_ids(20).unwrap();
+        assert!(ids.iter().all(|id| !(10..100).contains(id)));
+        assert_eq!(None, pool.request_id());
+        assert_eq!(Err(50), pool.return_id(50));
+        assert_eq!(
+            Err(IdPoolError::OutOfRange(50)),
+            pool.return_id_reporting(50)
+        );
+        assert_eq!(Ok(()), pool.return_id(105));
+        assert_eq!(Some(105), pool.request_id());
+        pool.assert_consistent();
+        assert_eq!(
+            Err(IdPoolError::InvalidRange),
+            IdPool::new_from_ranges(&[100..110, 0..10]).map(|_| ())
+        );
+        assert_eq!(
+            Err(IdPoolError::InvalidRange),
+            IdPool::new_from_ranges(&[0..10, 5..20]).map(|_| ())
+        );
+        assert_eq!(
+            Err(IdPoolError::InvalidRange),
+            IdPool::new_from_ranges(&[0..10, 20..20]).map(|_| ())
+        );
+        // adjacent ranges simply join up
+        let pool = IdPool::new_from_ranges(&[0..10, 10..20]).unwrap();
+        assert_eq!(20, pool.available());
+    }
+
+    #[test]
+    fn request_strided() {
+        let mut pool = IdPool::new_ranged(0..20);
+        assert_eq!(Some(vec![0, 5, 10, 15]), pool.request_strided(4, 5));
+        assert_eq!(None, pool.request_strided(2, 19));
+        assert_eq!(Some(vec![1, 6, 11, 16]), pool.request_strided(4, 5));
+        // a full pool has nothing left to space out
+        pool.request_ids(12).unwrap();
+        assert_eq!(None, pool.request_strided(1, 1));
+        for id in [2, 3, 9, 13, 19] {
+            pool.return_id(id).unwrap();
+        }
+        let ids = pool.request_strided(3, 6).unwrap();
+        assert_eq!(vec![2, 9, 19], ids);
+        assert!(ids.windows(2).all(|pair| pair[1] - pair[0] >= 6));
+        assert_eq!(None, pool.request_strided(2, 11));
+        assert_eq!(Some(vec![3, 13]), pool.request_strided(2, 10));
+        assert_eq!(20, pool.used_count());
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789".iter().copied()));
+        let mut pool = IdPool::new_ranged(1..10);
+        let fresh = pool.checksum();
+        assert_eq!(fresh, IdPool::new_ranged(1..10).checksum());
+        assert_ne!(fresh, IdPool::new_ranged(1..11).checksum());
+        pool.request_id();
+        assert_ne!(fresh, pool.checksum());
+        pool.return_id(1).unwrap();
+        assert_eq!(fresh, pool.checksum());
+    }
+
+    #[test]
+    #[cfg(feature = "hdrhistogram")]
+    fn latency_histogram() {
+        assert!(IdPool::new().latency_snapshot().is_none());
+        let mut pool = IdPool::new_ranged(0..10).with_latency_histogram();
+        for _ in 0..11 {
+            pool.request_id();
+        }
+        pool.return_id(3).unwrap();
+        assert_eq!(Err(3), pool.return_id(3));
+        let latency = pool.latency_snapshot().unwrap();
+        assert_eq!(13, latency.len());
+        assert!(latency.value_at_quantile(0.99) >= latency.min());
+    }
+
+    #[test]
+    fn request_ids_within_span() {
+        let mut pool = IdPool::new_ranged(0..40);
+        pool.request_ids(40).unwrap();
+        // scattered free ids, clustered more densely further up
+        for id in [0, 5, 10, 20, 22, 23, 25, 26, 30] {
+            pool.return_id(id).unwrap();
+        }
+        assert_eq!(None, pool.request_ids_within_span(6, 10));
+        let ids = pool.request_ids_within_span(4, 6).unwrap();
+        assert_eq!(vec![20, 22, 23, 25], ids);
+        assert!(ids.last().unwrap() - ids.first().unwrap() < 6);
+        let ids = pool.request_ids_within_span(2, 5).unwrap();
+        assert_eq!(vec![26, 30], ids);
+        assert_eq!(None, pool.request_ids_within_span(2, 5));
+        assert_eq!(Some(vec![0, 5]), pool.request_ids_within_span(2, 6));
+        assert_eq!(Some(vec![]), pool.request_ids_within_span(0, 0));
+        assert_eq!(39, pool.used_count());
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn reconcile() {
+        let mut pool = IdPool::new_ranged(0..20);
+        pool.request_ids(10).unwrap();
+        pool.block_id(15).unwrap();
+        let authority = [2, 3, 3, 7, 8, 9, 12, 13, 15, 25];
+        let report = pool.reconcile(&authority);
+        assert_eq!(vec![0..2, 4..7], report.freed);
+        assert_eq!(vec![12..14], report.reserved);
+        assert_eq!(2, report.ignored);
+        assert_eq!("[2-3, 7-9, 12-13]", pool.used_ranges_string());
+        assert_eq!(7, pool.used_count());
+        pool.assert_consistent();
+        // reconciling again changes nothing
+        let version = pool.version();
+        assert_eq!(
+            ReconcileReport {
+                ignored: 2,
+                ..ReconcileReport::default()
+            },
+            pool.reconcile(&authority)
+        );
+        assert_eq!(version, pool.version());
+        assert_eq!(Some(0), pool.request_id());
+    }
+
+    #[test]
+    fn reconcile_fragmented() {
+        let mut rng = testing::XorShift::new(0x5851_f42d_4c95_7f2d);
+        let mut next = move || rng.next_u64();
+        for _ in 0..50 {
+            let mut pool = IdPool::with_reserved_ranges(0..500, &[100..120, 300..310]).unwrap();
+            pool.request_ids(380).unwrap();
+            for id in 0..500 {
+                if next() % 2 == 0 {
+                    let _ = pool.return_id(id);
+                }
+            }
+            let _ = pool.block_id((next() % 500) as Num);
+            let authority: Vec<Num> = (0..600).filter(|_| next() % 3 == 0).collect();
+            let report = pool.reconcile(&authority);
+            pool.assert_consistent();
+            let expected: Vec<Num> = authority
+                .iter()
+                .copied()
+                .filter(|&id| {
+                    id < 500
+                        && !(100..120).contains(&id)
+                        && !(300..310).contains(&id)
+                        && !pool.blocked.contains(&id)
+                })
+                .collect();
+            assert_eq!(authority.len() - expected.len(), report.ignored);
+            assert_eq!(expected.len(), pool.used_count());
+            assert!(expected.iter().all(|&id| !pool.is_free(id)));
+            assert_eq!(
+                500 - 30 - pool.blocked.len(),
+                pool.used_count() + pool.available() as usize
+            );
+        }
+    }
+
+    #[test]
+    fn allocator() {
+        let mut pool = IdPool::new_ranged(0..5);
+        let ids: Vec<Num> = std::iter::from_fn(pool.allocator()).take(3).collect();
+        assert_eq!(vec![0, 1, 2], ids);
+        assert_eq!(3, pool.used_count());
+        // the iterator ends once the pool is exhausted
+        assert_eq!(2, std::iter::from_fn(pool.allocator()).count());
+        assert_eq!(None, pool.allocator()());
+        assert_eq!(5, pool.used_count());
+    }
+
+    #[test]
+    fn rollback_blocked_after_return() {
+        let mut pool = IdPool::new_ranged(0..10);
+        let (_, token) = pool.request_ids_with_token(4).unwrap();
+        pool.return_id(2).unwrap();
+        pool.block_id(2).unwrap();
+        assert_eq!(
+            Err(IdPoolError::NotAllocated(2)),
+            pool.rollback_allocation(token)
+        );
+        assert!(pool.is_blocked(2));
+        assert!(!pool.is_free(2));
+        assert_eq!(3, pool.used_count());
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn lazy_parts_round_trip() {
+        let mut pool = IdPool::new_ranged(0..10).with_lazy_coalescing(true);
+        pool.request_ids(6);
+        pool.return_id(3).unwrap();
+        pool.return_id(2).unwrap();
+        let checksum = pool.checksum();
+        let (range, free, used) = pool.into_parts();
+        assert_eq!(3, free.len());
+        let pool = IdPool::from_parts(range, free, used).unwrap();
+        assert_eq!(2, pool.free_range_count());
+        assert_eq!(checksum, pool.checksum());
+        pool.assert_consistent();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_without_range() {
+        let json = r#"{"free":[{"start":5,"end":10}],"used":4}"#;
+        let mut pool: IdPool = serde_json::from_str(json).unwrap();
+        pool.assert_consistent();
+        assert_eq!((1, 10), (pool.range.start, pool.range.end));
+        assert_eq!(4, pool.used_count());
+        assert_eq!(Some(5), pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(1));
+        assert_eq!(Err(1), pool.return_id(1));
+
+        let json = r#"{"free":[],"used":3}"#;
+        let pool: IdPool = serde_json::from_str(json).unwrap();
+        pool.assert_consistent();
+        assert_eq!(Num::MAX - 3, pool.range.start);
+
+        let json = r#"{"free":[{"start":5,"end":10}],"used":11}"#;
+        assert!(serde_json::from_str::<IdPool>(json).is_err());
+    }
+
+    #[test]
+    fn compact_free_list() {
+        let mut pool = IdPool::new_ranged(1_000..2_000);
+        pool.request_ids(1_000);
+        for id in (1_000..2_000).step_by(2) {
+            assert_eq!(Ok(()), pool.return_id(id));
+        }
+        pool.shrink_to_fit();
+        assert_eq!(500, pool.free_range_count());
+        let per_range = if cfg!(feature = "u64") {
+            std::mem::size_of::<u64>()
+        } else {
+            std::mem::size_of::<Range>()
+        };
+        assert_eq!(500 * per_range, pool.free.heap_size());
+        assert_eq!(Some(1_000), pool.request_id());
+        assert_eq!(Some(1_002), pool.request_id());
+        pool.assert_consistent();
+        // growing past the span of the offsets keeps the ranges
+        let before: Vec<_> = pool.free_ranges().collect();
+        assert_eq!(Ok(()), pool.grow_to(Num::MAX));
+        assert!(matches!(pool.free, FreeList::Wide(_)));
+        assert_eq!(Some(&(2_000..Num::MAX)), pool.free_ranges().last().as_ref());
+        assert!(pool.free_ranges().take(before.len()).eq(before));
+        pool.assert_consistent();
     }
 }
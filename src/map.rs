@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use crate::{IdPool, Num};
+
+/// Id pool keeping arbitrary data alongside every allocated id,
+/// making it usable much like a slab.
+///
+/// The data is attached when requesting an id and handed back
+/// once the id is returned to the pool.
+///
+/// # Examples
+///
+/// ```
+/// # use id_pool::IdMap;
+/// let mut map = IdMap::new();
+/// let id = map.request_id_with_data("worker").unwrap();
+/// assert_eq!(Some(&"worker"), map.data(id));
+/// assert_eq!(Ok("worker"), map.return_id(id));
+/// assert_eq!(None, map.data(id));
+/// ```
+#[derive(Debug, Clone)]
+pub struct IdMap<T> {
+    pool: IdPool,
+    /// Data attached to each allocated id
+    data: HashMap<Num, T>,
+}
+
+impl<T> Default for IdMap<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> IdMap<T> {
+    /// Creates a new `IdMap` with a default range, which starts
+    /// at `1` and ends at `Num::MAX`.
+    pub fn new() -> Self {
+        Self::new_ranged(1..Num::MAX)
+    }
+
+    /// Creates a new `IdMap` with the given range.
+    pub fn new_ranged(range: std::ops::Range<Num>) -> Self {
+        Self {
+            pool: IdPool::new_ranged(range),
+            data: HashMap::new(),
+        }
+    }
+
+    /// Gets the current count of used ids.
+    pub fn used_count(&self) -> usize {
+        self.pool.used_count()
+    }
+
+    /// Returns a new id with the given data attached to it, or
+    /// `Err(T)` handing the data back if there are no free ids
+    /// in the pool.
+    pub fn request_id_with_data(&mut self, data: T) -> Result<Num, T> {
+        match self.pool.request_id() {
+            Some(id) => {
+                self.data.insert(id, data);
+                Ok(id)
+            }
+            None => Err(data),
+        }
+    }
+
+    /// Gets the data attached to the given id, or `None` if the
+    /// id isn't allocated.
+    pub fn data(&self, id: Num) -> Option<&T> {
+        self.data.get(&id)
+    }
+
+    /// Gets the data attached to the given id mutably, or `None`
+    /// if the id isn't allocated.
+    pub fn data_mut(&mut self, id: Num) -> Option<&mut T> {
+        self.data.get_mut(&id)
+    }
+
+    /// Iterates over the allocated ids along with their data,
+    /// in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (Num, &T)> {
+        self.data.iter().map(|(&id, data)| (id, data))
+    }
+
+    /// Returns an id to the pool, handing back the data that
+    /// was attached to it, or `Err(Num)` if the id isn't
+    /// allocated.
+    pub fn return_id(&mut self, id: Num) -> Result<T, Num> {
+        let data = self.data.remove(&id).ok_or(id)?;
+        // every id with data attached is allocated from the pool
+        self.pool.return_id(id).unwrap();
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attached_data() {
+        let mut map = IdMap::new_ranged(0..2);
+        assert_eq!(Ok(0), map.request_id_with_data("first"));
+        assert_eq!(Ok(1), map.request_id_with_data("second"));
+        assert_eq!(Err("third"), map.request_id_with_data("third"));
+        *map.data_mut(1).unwrap() = "changed";
+        assert_eq!(Some(&"first"), map.data(0));
+        assert_eq!(Some(&"changed"), map.data(1));
+        assert_eq!(Ok("first"), map.return_id(0));
+        assert_eq!(Err(0), map.return_id(0));
+        assert_eq!(None, map.data(0));
+        assert_eq!(vec![(1, &"changed")], map.iter().collect::<Vec<_>>());
+        assert_eq!(Ok(0), map.request_id_with_data("third"));
+        assert_eq!(2, map.used_count());
+    }
+}
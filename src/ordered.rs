@@ -0,0 +1,112 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{IdPool, Num};
+
+/// Id pool keeping track of the order in which the allocated
+/// ids were handed out.
+///
+/// Allocation order approximates the age of each id, which is
+/// what LRU-style eviction needs, regardless of the numeric
+/// order recycling leaves the ids in.
+///
+/// # Examples
+///
+/// ```
+/// # use id_pool::OrderedIdPool;
+/// let mut pool = OrderedIdPool::new();
+/// assert_eq!(Some(1), pool.request_id());
+/// assert_eq!(Some(2), pool.request_id());
+/// assert_eq!(Ok(()), pool.return_id(1));
+/// assert_eq!(Some(1), pool.request_id());
+/// assert_eq!(vec![2, 1], pool.used_in_allocation_order().collect::<Vec<_>>());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct OrderedIdPool {
+    pool: IdPool,
+    /// Number of allocations made so far
+    allocations: u64,
+    /// Allocated ids, by the number of their allocation
+    order: BTreeMap<u64, Num>,
+    /// Number of the allocation of each allocated id
+    allocated_at: HashMap<Num, u64>,
+}
+
+impl OrderedIdPool {
+    /// Creates a new `OrderedIdPool` with a default range, which
+    /// starts at `1` and ends at `Num::MAX`.
+    pub fn new() -> Self {
+        Self::new_ranged(1..Num::MAX)
+    }
+
+    /// Creates a new `OrderedIdPool` with the given range.
+    pub fn new_ranged(range: std::ops::Range<Num>) -> Self {
+        Self {
+            pool: IdPool::new_ranged(range),
+            ..Self::default()
+        }
+    }
+
+    /// Gets the current count of used ids.
+    pub fn used_count(&self) -> usize {
+        self.pool.used_count()
+    }
+
+    /// Returns a new id or `None` if there are no free ids in
+    /// the pool.
+    pub fn request_id(&mut self) -> Option<Num> {
+        let id = self.pool.request_id()?;
+        self.order.insert(self.allocations, id);
+        self.allocated_at.insert(id, self.allocations);
+        self.allocations += 1;
+        Some(id)
+    }
+
+    /// Returns an id to the pool or `Err(Num)` if the id is
+    /// already in the pool.
+    pub fn return_id(&mut self, id: Num) -> Result<(), Num> {
+        self.pool.return_id(id)?;
+        if let Some(at) = self.allocated_at.remove(&id) {
+            self.order.remove(&at);
+        }
+        Ok(())
+    }
+
+    /// Iterates over the allocated ids in the order they were
+    /// allocated, oldest first.
+    pub fn used_in_allocation_order(&self) -> impl Iterator<Item = Num> + '_ {
+        self.order.values().copied()
+    }
+
+    /// Gets the allocated id that was handed out the longest
+    /// time ago.
+    pub fn oldest(&self) -> Option<Num> {
+        self.order.values().next().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocation_order() {
+        let mut pool = OrderedIdPool::new_ranged(0..10);
+        for _ in 0..5 {
+            pool.request_id();
+        }
+        assert_eq!(Ok(()), pool.return_id(1));
+        assert_eq!(Ok(()), pool.return_id(3));
+        assert_eq!(Err(3), pool.return_id(3));
+        // recycled ids come back lowest first, but are the youngest
+        assert_eq!(Some(1), pool.request_id());
+        assert_eq!(Some(3), pool.request_id());
+        assert_eq!(Some(5), pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(0));
+        assert_eq!(
+            vec![2, 4, 1, 3, 5],
+            pool.used_in_allocation_order().collect::<Vec<_>>()
+        );
+        assert_eq!(Some(2), pool.oldest());
+        assert_eq!(5, pool.used_count());
+    }
+}
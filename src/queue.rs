@@ -0,0 +1,151 @@
+use std::sync::Mutex;
+
+use crossbeam_queue::ArrayQueue;
+
+use crate::{IdPool, Num};
+
+/// Source of ids preallocated from an [`IdPool`] into a
+/// lock-free queue, so that threads can take and return ids
+/// without locking the pool most of the time.
+///
+/// The queue is refilled in a batch once it drops below a
+/// threshold. Returned ids are collected in a second queue and
+/// reclaimed by the pool in a batch once that one fills up.
+/// Returning an id that isn't allocated is not detected until
+/// the batch is reclaimed, where such ids are ignored.
+///
+/// [`IdPool`]: struct.IdPool.html
+#[derive(Debug)]
+pub struct QueuedIdSource {
+    pool: Mutex<IdPool>,
+    /// Preallocated ids ready to be handed out
+    ids: ArrayQueue<Num>,
+    /// Returned ids waiting to be reclaimed by the pool
+    returned: ArrayQueue<Num>,
+    /// Number of preallocated ids below which the queue is
+    /// refilled
+    threshold: usize,
+}
+
+impl QueuedIdSource {
+    /// Creates a new source preallocating up to `capacity` ids
+    /// from `pool`, refilling once fewer than `threshold` are
+    /// left.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new(pool: IdPool, capacity: usize, threshold: usize) -> Self {
+        let source = Self {
+            pool: Mutex::new(pool),
+            ids: ArrayQueue::new(capacity),
+            returned: ArrayQueue::new(capacity),
+            threshold,
+        };
+        source.refill();
+        source
+    }
+
+    /// Returns a new id or `None` if there are no free ids left,
+    /// neither preallocated nor in the pool. Close to exhaustion,
+    /// this may miss ids being returned by other threads at the
+    /// same time.
+    pub fn request_id(&self) -> Option<Num> {
+        if self.ids.len() < self.threshold {
+            self.refill();
+        }
+        match self.ids.pop() {
+            Some(id) => Some(id),
+            None => {
+                self.refill();
+                self.ids.pop()
+            }
+        }
+    }
+
+    /// Returns an id, reclaiming the returned ids into the pool
+    /// once enough of them have been collected.
+    pub fn return_id(&self, id: Num) {
+        let mut id = id;
+        while let Err(rejected) = self.returned.push(id) {
+            self.reclaim(&mut self.pool.lock().unwrap());
+            id = rejected;
+        }
+    }
+
+    /// Consumes the source, putting all of the preallocated and
+    /// returned ids back into the pool and returning it.
+    pub fn into_inner(self) -> IdPool {
+        let mut pool = self.pool.into_inner().unwrap();
+        for queue in [self.ids, self.returned] {
+            while let Some(id) = queue.pop() {
+                let _ = pool.return_id(id);
+            }
+        }
+        pool
+    }
+
+    /// Tops up the preallocated ids, reclaiming the returned ids
+    /// into the pool first.
+    fn refill(&self) {
+        let mut pool = self.pool.lock().unwrap();
+        self.reclaim(&mut pool);
+        let missing = self.ids.capacity() - self.ids.len();
+        let count = missing.min(pool.available() as usize);
+        // there are enough free ids for the batch
+        for id in pool.request_ids(count).unwrap_or_default() {
+            if let Err(id) = self.ids.push(id) {
+                // other threads may have returned ids meanwhile
+                let _ = pool.return_id(id);
+            }
+        }
+    }
+
+    /// Moves the returned ids back into the pool.
+    fn reclaim(&self, pool: &mut IdPool) {
+        while let Some(id) = self.returned.pop() {
+            let _ = pool.return_id(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn concurrent_queue() {
+        let source = Arc::new(QueuedIdSource::new(IdPool::new_ranged(0..1200), 64, 16));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let source = source.clone();
+                thread::spawn(move || {
+                    let ids: Vec<Num> = (0..250).map(|_| source.request_id().unwrap()).collect();
+                    // hand some back and take them again
+                    for &id in &ids[..100] {
+                        source.return_id(id);
+                    }
+                    let mut kept = ids[100..].to_vec();
+                    kept.extend((0..100).map(|_| source.request_id().unwrap()));
+                    kept
+                })
+            })
+            .collect();
+        let mut ids: Vec<Num> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        ids.extend(std::iter::from_fn(|| source.request_id()));
+        assert_eq!(1200, ids.iter().collect::<HashSet<_>>().len());
+        for id in ids {
+            source.return_id(id);
+        }
+        let pool = Arc::try_unwrap(source).unwrap().into_inner();
+        assert_eq!(0, pool.used_count());
+        pool.assert_consistent();
+    }
+}
@@ -0,0 +1,101 @@
+use std::fmt;
+
+use rand_core::RngCore;
+
+use crate::{IdPool, Num};
+
+/// Id pool handing out free ids at random, drawing from a
+/// caller-supplied random number generator.
+///
+/// Supplying a seeded generator makes the allocation sequence
+/// reproducible, which is useful for tests and replays. Create
+/// one with [`IdPool::new_random_with_rng`].
+///
+/// [`IdPool::new_random_with_rng`]: struct.IdPool.html#method.new_random_with_rng
+pub struct RandomIdPool {
+    pool: IdPool,
+    rng: Box<dyn RngCore + Send>,
+}
+
+impl fmt::Debug for RandomIdPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RandomIdPool")
+            .field("pool", &self.pool)
+            .finish()
+    }
+}
+
+impl IdPool {
+    /// Creates a new `RandomIdPool` with the given range, which
+    /// picks ids using the given random number generator.
+    pub fn new_random_with_rng<R: RngCore + Send + 'static>(
+        range: std::ops::Range<Num>,
+        rng: R,
+    ) -> RandomIdPool {
+        RandomIdPool {
+            pool: IdPool::new_ranged(range),
+            rng: Box::new(rng),
+        }
+    }
+}
+
+impl RandomIdPool {
+    /// Gets the current count of used ids.
+    pub fn used_count(&self) -> usize {
+        self.pool.used_count()
+    }
+
+    /// Returns a random free id or `None` if there are no free
+    /// ids in the pool.
+    pub fn request_id(&mut self) -> Option<Num> {
+        let available = self.pool.available();
+        if available == 0 {
+            return None;
+        }
+        let n = (self.rng.next_u64() % available as u64) as Num;
+        let id = self.pool.nth_free(n)?;
+        self.pool.allocate(id);
+        Some(id)
+    }
+
+    /// Returns an id to the pool or `Err(Num)` if the id is
+    /// already in the pool.
+    pub fn return_id(&mut self, id: Num) -> Result<(), Num> {
+        self.pool.return_id(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::XorShift;
+
+    fn sequence(seed: u64) -> Vec<Num> {
+        let mut pool = IdPool::new_random_with_rng(1..100, XorShift::new(seed));
+        (0..20).map(|_| pool.request_id().unwrap()).collect()
+    }
+
+    #[test]
+    fn reproducible_sequence() {
+        let ids = sequence(42);
+        assert_eq!(ids, sequence(42));
+        assert_ne!(ids, sequence(7));
+        let mut sorted = ids.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert_eq!(20, sorted.len());
+        assert_ne!(sorted, ids);
+    }
+
+    #[test]
+    fn exhaust_and_return() {
+        let mut pool = IdPool::new_random_with_rng(1..4, XorShift::new(1));
+        let mut ids: Vec<Num> = (0..3).map(|_| pool.request_id().unwrap()).collect();
+        assert_eq!(None, pool.request_id());
+        ids.sort_unstable();
+        assert_eq!(vec![1, 2, 3], ids);
+        assert_eq!(Ok(()), pool.return_id(2));
+        assert_eq!(Some(2), pool.request_id());
+        assert_eq!(3, pool.used_count());
+    }
+}
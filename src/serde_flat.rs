@@ -0,0 +1,277 @@
+//! Flat serde representation of [`IdPool`], for use with
+//! `#[serde(with = "id_pool::serde_flat")]`.
+//!
+//! The pool is serialized as a single array of `[start, end]`
+//! pairs: the configured range first, followed by the free
+//! ranges in ascending order. This embeds nicely as a plain
+//! array field of a larger structure. The used count is
+//! recomputed on deserialization, blocked and reserved ids
+//! are not preserved and are counted as used instead.
+//!
+//! ```
+//! # use id_pool::IdPool;
+//! # use serde::{Deserialize, Serialize};
+//! #[derive(Serialize, Deserialize)]
+//! struct Registry {
+//!     name: String,
+//!     #[serde(with = "id_pool::serde_flat")]
+//!     ids: IdPool,
+//! }
+//! ```
+//!
+//! For pools with many free ranges, the [`delta`] submodule
+//! provides a variant of the same form with smaller payloads,
+//! while the [`checked`] submodule stores a checksum alongside
+//! the ranges to detect corruption.
+//!
+//! [`IdPool`]: ../struct.IdPool.html
+//! [`delta`]: delta/index.html
+//! [`checked`]: checked/index.html
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{IdPool, Num, Range};
+
+/// Serializes the pool as a flat array of ranges.
+pub fn serialize<S: Serializer>(pool: &IdPool, serializer: S) -> Result<S::Ok, S::Error> {
+    let ranges: Vec<(Num, Num)> = std::iter::once((pool.range.start, pool.range.end))
+        .chain(pool.free_ranges().map(|range| (range.start, range.end)))
+        .collect();
+    ranges.serialize(serializer)
+}
+
+/// Deserializes a pool from a flat array of ranges.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<IdPool, D::Error> {
+    let ranges = Vec::<(Num, Num)>::deserialize(deserializer)?;
+    from_ranges(&ranges).map_err(D::Error::custom)
+}
+
+/// Creates a pool from the configured range followed by the
+/// free ranges in ascending order.
+fn from_ranges(ranges: &[(Num, Num)]) -> Result<IdPool, &'static str> {
+    let (&(start, end), free) = ranges.split_first().ok_or("missing configured range")?;
+    let free: Vec<Range> = free
+        .iter()
+        .rev()
+        .map(|&(start, end)| Range { start, end })
+        .collect();
+    let free_count: Num = free
+        .iter()
+        .map(|range| range.end.saturating_sub(range.start))
+        .sum();
+    let used = end.saturating_sub(start).saturating_sub(free_count) as usize;
+    IdPool::from_parts(start..end, free, used).map_err(|_| "invalid free ranges")
+}
+
+/// Delta-encoded variant of the flat representation, for use
+/// with `#[serde(with = "id_pool::serde_flat::delta")]`.
+///
+/// The configured range is stored as is, while each free range
+/// is stored as a `[gap, len]` pair: the distance of its start
+/// from the end of the previous free range, or from the start
+/// of the configured range for the first one, and its length.
+/// Since these are typically much smaller than the absolute
+/// bounds, payloads shrink with size-sensitive formats.
+pub mod delta {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::{IdPool, Num};
+
+    /// Serializes the pool as a delta-encoded array of ranges.
+    pub fn serialize<S: Serializer>(pool: &IdPool, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut ranges = vec![(pool.range.start, pool.range.end)];
+        let mut last = pool.range.start;
+        for range in pool.free_ranges() {
+            ranges.push((range.start - last, range.end - range.start));
+            last = range.end;
+        }
+        ranges.serialize(serializer)
+    }
+
+    /// Deserializes a pool from a delta-encoded array of ranges.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<IdPool, D::Error> {
+        let mut ranges = Vec::<(Num, Num)>::deserialize(deserializer)?;
+        // turn the deltas back into absolute bounds in place
+        let mut last = ranges.first().map_or(0, |&(start, _)| start);
+        for range in ranges.iter_mut().skip(1) {
+            let start = last
+                .checked_add(range.0)
+                .ok_or_else(|| D::Error::custom("range out of bounds"))?;
+            last = start
+                .checked_add(range.1)
+                .ok_or_else(|| D::Error::custom("range out of bounds"))?;
+            *range = (start, last);
+        }
+        super::from_ranges(&ranges).map_err(D::Error::custom)
+    }
+}
+
+/// Checksummed variant of the flat representation, for use
+/// with `#[serde(with = "id_pool::serde_flat::checked")]`.
+///
+/// The pool is stored as a pair of the flat array of ranges and
+/// its [`IdPool::checksum`]. The checksum is recomputed when
+/// deserializing, and a mismatch is reported as an error, which
+/// catches bit-rot and truncated payloads.
+///
+/// [`IdPool::checksum`]: ../../struct.IdPool.html#method.checksum
+pub mod checked {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::{IdPool, Num};
+
+    /// Serializes the pool as a flat array of ranges along with
+    /// its checksum.
+    pub fn serialize<S: Serializer>(pool: &IdPool, serializer: S) -> Result<S::Ok, S::Error> {
+        let ranges: Vec<(Num, Num)> = std::iter::once((pool.range.start, pool.range.end))
+            .chain(pool.free_ranges().map(|range| (range.start, range.end)))
+            .collect();
+        (ranges, pool.checksum()).serialize(serializer)
+    }
+
+    /// Deserializes a pool from a flat array of ranges, checking
+    /// it against the stored checksum.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<IdPool, D::Error> {
+        let (ranges, checksum) = <(Vec<(Num, Num)>, u32)>::deserialize(deserializer)?;
+        let pool = super::from_ranges(&ranges).map_err(D::Error::custom)?;
+        if pool.checksum() != checksum {
+            return Err(D::Error::custom("checksum mismatch"));
+        }
+        Ok(pool)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use crate::IdPool;
+
+    #[derive(Serialize, Deserialize)]
+    struct Parent {
+        name: String,
+        #[serde(with = "crate::serde_flat")]
+        ids: IdPool,
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut ids = IdPool::new_ranged(1..10);
+        ids.request_ids(5);
+        assert_eq!(Ok(()), ids.return_id(2));
+        let parent = Parent {
+            name: "sessions".to_string(),
+            ids,
+        };
+        let json = serde_json::to_string(&parent).unwrap();
+        assert_eq!(r#"{"name":"sessions","ids":[[1,10],[2,3],[6,10]]}"#, json);
+        let parent: Parent = serde_json::from_str(&json).unwrap();
+        assert_eq!("sessions", parent.name);
+        assert_eq!(4, parent.ids.used_count());
+        assert_eq!("[2, 6-9]", parent.ids.free_ranges_string());
+
+        // overlapping free ranges are rejected
+        let json = r#"{"name":"sessions","ids":[[1,10],[2,5],[4,10]]}"#;
+        assert!(serde_json::from_str::<Parent>(json).is_err());
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct DeltaParent {
+        #[serde(with = "crate::serde_flat::delta")]
+        ids: IdPool,
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let mut ids = IdPool::new_ranged(1..10);
+        ids.request_ids(5);
+        assert_eq!(Ok(()), ids.return_id(2));
+        let json = serde_json::to_string(&DeltaParent { ids }).unwrap();
+        assert_eq!(r#"{"ids":[[1,10],[1,1],[3,4]]}"#, json);
+        let parent: DeltaParent = serde_json::from_str(&json).unwrap();
+        assert_eq!(4, parent.ids.used_count());
+        assert_eq!("[2, 6-9]", parent.ids.free_ranges_string());
+
+        // deltas running past the end of `Num` are rejected
+        let json = format!(r#"{{"ids":[[1,10],[{}, 1]]}}"#, crate::Num::MAX);
+        assert!(serde_json::from_str::<DeltaParent>(&json).is_err());
+    }
+
+    #[test]
+    fn delta_no_larger() {
+        let mut ids = IdPool::new_ranged(10_000..60_000);
+        ids.request_ids(3_000);
+        for id in (10_000..13_000).step_by(3) {
+            assert_eq!(Ok(()), ids.return_id(id));
+        }
+        let absolute = serde_json::to_string(&Parent {
+            name: String::new(),
+            ids: ids.clone(),
+        })
+        .unwrap();
+        let delta = serde_json::to_string(&DeltaParent { ids }).unwrap();
+        assert!(delta.len() <= absolute.len());
+        assert!(delta.len() < absolute.len() / 2);
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct CheckedParent {
+        #[serde(with = "crate::serde_flat::checked")]
+        ids: IdPool,
+    }
+
+    #[test]
+    fn checked_round_trip() {
+        let mut ids = IdPool::new_ranged(1..10);
+        ids.request_ids(5);
+        assert_eq!(Ok(()), ids.return_id(2));
+        let checksum = ids.checksum();
+        let json = serde_json::to_string(&CheckedParent { ids }).unwrap();
+        assert_eq!(
+            format!(r#"{{"ids":[[[1,10],[2,3],[6,10]],{}]}}"#, checksum),
+            json
+        );
+        let parent: CheckedParent = serde_json::from_str(&json).unwrap();
+        assert_eq!(checksum, parent.ids.checksum());
+        assert_eq!("[2, 6-9]", parent.ids.free_ranges_string());
+
+        // a corrupted bound no longer matches the checksum
+        let corrupted = json.replacen("[6,10]", "[7,10]", 1);
+        let err = serde_json::from_str::<CheckedParent>(&corrupted)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("checksum mismatch"));
+        // and neither does a truncated list of ranges
+        let truncated = json.replacen(",[6,10]", "", 1);
+        assert!(serde_json::from_str::<CheckedParent>(&truncated).is_err());
+    }
+
+    #[test]
+    fn lazy_coalescing_round_trip() {
+        let mut ids = IdPool::new_ranged(0..10).with_lazy_coalescing(true);
+        ids.request_ids(6);
+        assert_eq!(Ok(()), ids.return_id(3));
+        assert_eq!(Ok(()), ids.return_id(2));
+        let parent = Parent {
+            name: String::new(),
+            ids: ids.clone(),
+        };
+        let json = serde_json::to_string(&parent).unwrap();
+        assert!(json.ends_with(r#""ids":[[0,10],[2,3],[3,4],[6,10]]}"#));
+        let parent: Parent = serde_json::from_str(&json).unwrap();
+        assert_eq!("[2-3, 6-9]", parent.ids.free_ranges_string());
+        assert_eq!(4, parent.ids.used_count());
+        parent.ids.assert_consistent();
+
+        let json = serde_json::to_string(&DeltaParent { ids: ids.clone() }).unwrap();
+        let parent: DeltaParent = serde_json::from_str(&json).unwrap();
+        assert_eq!("[2-3, 6-9]", parent.ids.free_ranges_string());
+
+        let json = serde_json::to_string(&CheckedParent { ids }).unwrap();
+        let parent: CheckedParent = serde_json::from_str(&json).unwrap();
+        assert_eq!("[2-3, 6-9]", parent.ids.free_ranges_string());
+    }
+}
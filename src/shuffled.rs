@@ -0,0 +1,124 @@
+use crate::Num;
+
+/// Id pool handing out ids in a shuffled order, fixed by a seed
+/// at construction.
+///
+/// The whole range is shuffled up front, so every request is a
+/// constant-time pop without any random number generation, and
+/// the same seed always yields the same sequence. Returned ids
+/// are handed out again before the rest of the sequence. As all
+/// ids are held in memory, this only suits bounded ranges.
+///
+/// # Examples
+///
+/// ```
+/// # use id_pool::ShuffledIdPool;
+/// let mut pool = ShuffledIdPool::new(0..100, 42);
+/// let id = pool.request_id().unwrap();
+/// assert_eq!(Ok(()), pool.return_id(id));
+/// assert_eq!(Some(id), pool.request_id());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ShuffledIdPool {
+    /// Start of the configured range
+    start: Num,
+    /// Free ids, the next one to hand out coming last
+    free: Vec<Num>,
+    /// Whether each id of the range is allocated, by offset
+    /// from the start
+    allocated: Vec<bool>,
+}
+
+impl ShuffledIdPool {
+    /// Creates a new `ShuffledIdPool` with the given range,
+    /// shuffling its ids based on `seed`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is inverted.
+    pub fn new(range: std::ops::Range<Num>, seed: u64) -> Self {
+        assert!(
+            range.start <= range.end,
+            "{}",
+            crate::IdPoolError::InvalidRange
+        );
+        let mut free: Vec<Num> = range.clone().collect();
+        let mut state = seed;
+        let mut next = move || crate::splitmix64(&mut state);
+        // Fisher-Yates shuffle
+        for i in (1..free.len()).rev() {
+            let j = (next() % (i as u64 + 1)) as usize;
+            free.swap(i, j);
+        }
+        Self {
+            start: range.start,
+            allocated: vec![false; free.len()],
+            free,
+        }
+    }
+
+    /// Gets the current count of used ids.
+    pub fn used_count(&self) -> usize {
+        self.allocated.len() - self.free.len()
+    }
+
+    /// Gets the current count of free ids.
+    pub fn available(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Returns the next id of the shuffled sequence or `None`
+    /// if there are no free ids in the pool.
+    pub fn request_id(&mut self) -> Option<Num> {
+        let id = self.free.pop()?;
+        self.allocated[(id - self.start) as usize] = true;
+        Some(id)
+    }
+
+    /// Returns an id to the pool or `Err(Num)` if the id is
+    /// already in the pool or lies outside of the configured
+    /// range.
+    pub fn return_id(&mut self, id: Num) -> Result<(), Num> {
+        let offset = id.checked_sub(self.start).ok_or(id)? as usize;
+        match self.allocated.get_mut(offset) {
+            Some(allocated) if *allocated => {
+                *allocated = false;
+                self.free.push(id);
+                Ok(())
+            }
+            _ => Err(id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shuffled_order() {
+        let drain = |mut pool: ShuffledIdPool| -> Vec<Num> {
+            std::iter::from_fn(|| pool.request_id()).collect()
+        };
+        let ids = drain(ShuffledIdPool::new(10..110, 7));
+        assert_eq!(ids, drain(ShuffledIdPool::new(10..110, 7)));
+        assert_ne!(ids, drain(ShuffledIdPool::new(10..110, 8)));
+        assert_ne!((10..110).collect::<Vec<_>>(), ids);
+        let mut sorted = ids.clone();
+        sorted.sort_unstable();
+        assert_eq!((10..110).collect::<Vec<_>>(), sorted);
+
+        let mut pool = ShuffledIdPool::new(10..110, 7);
+        let first = pool.request_id().unwrap();
+        assert_eq!(ids[0], first);
+        assert_eq!(Err(ids[1]), pool.return_id(ids[1]));
+        assert_eq!(Err(9), pool.return_id(9));
+        assert_eq!(Err(110), pool.return_id(110));
+        assert_eq!(Ok(()), pool.return_id(first));
+        assert_eq!(Err(first), pool.return_id(first));
+        assert_eq!(Some(first), pool.request_id());
+        assert_eq!(Some(ids[1]), pool.request_id());
+        assert_eq!(2, pool.used_count());
+        assert_eq!(98, pool.available());
+    }
+}
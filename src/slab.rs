@@ -0,0 +1,130 @@
+use crate::{IdPool, Num};
+
+/// Slab storing values under ids allocated from an [`IdPool`],
+/// with constant-time access by id.
+///
+/// Values are kept in a vector indexed by the offset of their id
+/// from the start of the range, growing up to the highest id in
+/// use. Ids of removed values are reused, lowest first.
+///
+/// # Examples
+///
+/// ```
+/// # use id_pool::IdSlab;
+/// let mut slab = IdSlab::new();
+/// let id = slab.insert("value");
+/// assert_eq!(Some(&"value"), slab.get(id));
+/// assert_eq!(Some("value"), slab.remove(id));
+/// assert_eq!(None, slab.get(id));
+/// ```
+///
+/// [`IdPool`]: struct.IdPool.html
+#[derive(Debug, Clone)]
+pub struct IdSlab<T> {
+    pool: IdPool,
+    /// Values by offset of their id from the start of the range
+    values: Vec<Option<T>>,
+}
+
+impl<T> Default for IdSlab<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> IdSlab<T> {
+    /// Creates a new `IdSlab` with a default range, which starts
+    /// at `1` and ends at `Num::MAX`.
+    pub fn new() -> Self {
+        Self::new_ranged(1..Num::MAX)
+    }
+
+    /// Creates a new `IdSlab` with the given range.
+    pub fn new_ranged(range: std::ops::Range<Num>) -> Self {
+        Self {
+            pool: IdPool::new_ranged(range),
+            values: Vec::new(),
+        }
+    }
+
+    /// Gets the number of stored values.
+    pub fn len(&self) -> usize {
+        self.pool.used_count()
+    }
+
+    /// Checks whether no values are stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Stores the value under a new id, returning the id.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no free ids left.
+    pub fn insert(&mut self, value: T) -> Num {
+        let id = match self.pool.try_request_id() {
+            Ok(id) => id,
+            Err(e) => panic!("{}", e),
+        };
+        let index = self.index(id).unwrap();
+        if index >= self.values.len() {
+            self.values.resize_with(index + 1, || None);
+        }
+        self.values[index] = Some(value);
+        id
+    }
+
+    /// Gets the value stored under the given id.
+    pub fn get(&self, id: Num) -> Option<&T> {
+        self.values.get(self.index(id)?)?.as_ref()
+    }
+
+    /// Gets the value stored under the given id mutably.
+    pub fn get_mut(&mut self, id: Num) -> Option<&mut T> {
+        let index = self.index(id)?;
+        self.values.get_mut(index)?.as_mut()
+    }
+
+    /// Removes the value stored under the given id, returning
+    /// the id to the pool. Returns `None` if there is no value
+    /// stored under the id.
+    pub fn remove(&mut self, id: Num) -> Option<T> {
+        let index = self.index(id)?;
+        let value = self.values.get_mut(index)?.take()?;
+        // every stored value holds an id allocated from the pool
+        self.pool.return_id(id).unwrap();
+        Some(value)
+    }
+
+    /// Gets the index of the value slot for the given id.
+    fn index(&self, id: Num) -> Option<usize> {
+        id.checked_sub(self.pool.range.start).map(|n| n as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slab() {
+        let mut slab = IdSlab::new_ranged(10..20);
+        assert_eq!(10, slab.insert("a"));
+        assert_eq!(11, slab.insert("b"));
+        assert_eq!(12, slab.insert("c"));
+        assert_eq!(3, slab.len());
+        *slab.get_mut(11).unwrap() = "B";
+        assert_eq!(Some(&"B"), slab.get(11));
+        assert_eq!(None, slab.get(5));
+        assert_eq!(None, slab.get(13));
+        assert_eq!(Some("B"), slab.remove(11));
+        assert_eq!(None, slab.remove(11));
+        assert_eq!(None, slab.get(11));
+        assert_eq!(2, slab.len());
+        // the removed id is reused for the next value
+        assert_eq!(11, slab.insert("d"));
+        assert_eq!(Some(&"d"), slab.get(11));
+        assert_eq!(Some(&"c"), slab.get(12));
+    }
+}
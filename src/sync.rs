@@ -0,0 +1,346 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::{IdPool, Num};
+
+/// Threads seen so far, used to spread them across the shards.
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Shard the current thread requests ids from first.
+    static HOME_SHARD: usize = THREADS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Id pool that can be shared between threads, e.g. by
+/// wrapping it in an `Arc`.
+///
+/// The id space can be split into shards, each guarded by its
+/// own lock, so that concurrent requests usually lock different
+/// shards. Threads are spread across the shards, each falling
+/// back to the other shards once its own is exhausted, while
+/// returned ids are routed to the shard owning them. With a single shard,
+/// the lowest free id is always handed out first, just like
+/// with [`IdPool`]. With more shards, ids are only lowest-first
+/// within each shard.
+///
+/// [`IdPool`]: struct.IdPool.html
+#[derive(Debug)]
+pub struct SyncIdPool {
+    /// Pools over consecutive parts of the configured range
+    shards: Vec<Mutex<IdPool>>,
+    /// Starts of the shard ranges, in ascending order
+    starts: Vec<Num>,
+    /// Number of threads waiting for an id to be returned
+    waiters: AtomicUsize,
+    /// Lock paired with the `returned` condition variable
+    gate: Mutex<()>,
+    /// Wakes up threads waiting for an id to be returned
+    returned: Condvar,
+}
+
+impl Default for SyncIdPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SyncIdPool {
+    /// Creates a new `SyncIdPool` with a default range, which
+    /// starts at `1` and ends at `Num::MAX`.
+    pub fn new() -> Self {
+        Self::new_ranged(1..Num::MAX)
+    }
+
+    /// Creates a new `SyncIdPool` with the given range.
+    pub fn new_ranged(range: std::ops::Range<Num>) -> Self {
+        Self::from(IdPool::new_ranged(range))
+    }
+
+    /// Creates a new `SyncIdPool` with the given range, split
+    /// into `shards` parts of equal width.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero or the range is inverted.
+    pub fn with_shards(range: std::ops::Range<Num>, shards: usize) -> Self {
+        assert!(shards != 0, "shard count must be non-zero");
+        let mut pool = IdPool::new_ranged(range.clone());
+        // wide enough for the shards to cover the whole range
+        let width = ((range.end - range.start) as usize).div_ceil(shards).max(1) as Num;
+        let mut parts = Vec::with_capacity(shards);
+        for i in (1..shards).rev() {
+            let at = range.start.saturating_add(width.saturating_mul(i as Num));
+            parts.push(pool.split_off(at.min(range.end)));
+        }
+        parts.push(pool);
+        parts.reverse();
+        Self::from_shards(parts)
+    }
+
+    /// Creates a `SyncIdPool` from pools over consecutive ranges.
+    fn from_shards(shards: Vec<IdPool>) -> Self {
+        Self {
+            starts: shards.iter().map(|pool| pool.range.start).collect(),
+            shards: shards.into_iter().map(Mutex::new).collect(),
+            waiters: AtomicUsize::new(0),
+            gate: Mutex::new(()),
+            returned: Condvar::new(),
+        }
+    }
+
+    /// Gets the number of shards the id space is split into.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Gets the current count of used ids.
+    pub fn used_count(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().used_count())
+            .sum()
+    }
+
+    /// Gets the current count of free ids.
+    pub fn available(&self) -> Num {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().available())
+            .sum()
+    }
+
+    /// Returns a new id or `None` if there are no free ids in
+    /// the pool.
+    pub fn request_id(&self) -> Option<Num> {
+        let first = match self.shards.len() {
+            1 => 0,
+            len => HOME_SHARD.with(|&shard| shard) % len,
+        };
+        (first..self.shards.len())
+            .chain(0..first)
+            .find_map(|i| self.shards[i].lock().unwrap().request_id())
+    }
+
+    /// Returns an id to the pool or `Err(Num)` if the id is
+    /// already in the pool.
+    pub fn return_id(&self, id: Num) -> Result<(), Num> {
+        self.shard(id).lock().unwrap().return_id(id)?;
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            // taking the lock makes sure a waiter that has just
+            // found the pool exhausted is parked before being notified
+            let _gate = self.gate.lock().unwrap();
+            self.returned.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Returns a guard holding a new id, which returns the id to
+    /// the pool once dropped, or `None` if there are no free ids
+    /// in the pool.
+    pub fn acquire(self: &Arc<Self>) -> Option<SyncIdGuard> {
+        let id = self.request_id()?;
+        Some(SyncIdGuard {
+            pool: self.clone(),
+            id,
+        })
+    }
+
+    /// Consumes the wrapper, returning the underlying pool with
+    /// all of the shards joined back together.
+    pub fn into_inner(self) -> IdPool {
+        let mut shards = self
+            .shards
+            .into_iter()
+            .map(|shard| shard.into_inner().unwrap());
+        // there is always at least one shard
+        let mut pool = shards.next().unwrap();
+        for shard in shards {
+            pool.append(shard);
+        }
+        pool
+    }
+
+    /// Returns a new id, waiting for one to be returned to the
+    /// pool if there are no free ids. Returns `None` once `stop`
+    /// is set, see [`wake_all`].
+    ///
+    /// [`wake_all`]: #method.wake_all
+    #[cfg_attr(not(feature = "buffered"), allow(dead_code))]
+    pub(crate) fn wait_request_id(&self, stop: &AtomicBool) -> Option<Num> {
+        if stop.load(Ordering::SeqCst) {
+            return None;
+        }
+        if let Some(id) = self.request_id() {
+            return Some(id);
+        }
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let mut gate = self.gate.lock().unwrap();
+        let id = loop {
+            if stop.load(Ordering::SeqCst) {
+                break None;
+            }
+            if let Some(id) = self.request_id() {
+                break Some(id);
+            }
+            gate = self.returned.wait(gate).unwrap();
+        };
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        id
+    }
+
+    /// Wakes up all threads waiting in [`wait_request_id`], so
+    /// they can notice a change of their stop flag.
+    ///
+    /// [`wait_request_id`]: #method.wait_request_id
+    #[cfg_attr(not(feature = "buffered"), allow(dead_code))]
+    pub(crate) fn wake_all(&self) {
+        // taking the lock makes sure a waiter that has already
+        // checked its stop flag is parked before being notified
+        let _gate = self.gate.lock().unwrap();
+        self.returned.notify_all();
+    }
+
+    /// Gets the shard owning the given id.
+    fn shard(&self, id: Num) -> &Mutex<IdPool> {
+        let i = self.starts.partition_point(|&start| start <= id);
+        &self.shards[i.saturating_sub(1)]
+    }
+}
+
+impl From<IdPool> for SyncIdPool {
+    fn from(pool: IdPool) -> Self {
+        Self::from_shards(vec![pool])
+    }
+}
+
+/// Id acquired from a [`SyncIdPool`], returned to the pool
+/// automatically when the guard is dropped. Created with
+/// [`SyncIdPool::acquire`].
+///
+/// The guard keeps the pool alive and can be sent to other
+/// threads.
+///
+/// [`SyncIdPool`]: struct.SyncIdPool.html
+/// [`SyncIdPool::acquire`]: struct.SyncIdPool.html#method.acquire
+#[derive(Debug)]
+pub struct SyncIdGuard {
+    pool: Arc<SyncIdPool>,
+    id: Num,
+}
+
+impl SyncIdGuard {
+    /// Gets the acquired id.
+    pub fn id(&self) -> Num {
+        self.id
+    }
+}
+
+impl std::ops::Deref for SyncIdGuard {
+    type Target = Num;
+
+    fn deref(&self) -> &Num {
+        &self.id
+    }
+}
+
+impl Drop for SyncIdGuard {
+    fn drop(&mut self) {
+        // the id stays allocated for as long as the guard lives
+        let _ = self.pool.return_id(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn concurrent_requests() {
+        let pool = Arc::new(SyncIdPool::new_ranged(0..1000));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    (0..250)
+                        .map(|_| pool.request_id().unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let ids: HashSet<Num> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        assert_eq!(1000, ids.len());
+        assert_eq!(None, pool.request_id());
+        assert_eq!(Ok(()), pool.return_id(10));
+        assert_eq!(Err(10), pool.return_id(10));
+        assert_eq!(999, pool.used_count());
+    }
+
+    #[test]
+    fn sharded() {
+        let pool = Arc::new(SyncIdPool::with_shards(0..1003, 4));
+        assert_eq!(4, pool.shard_count());
+        assert_eq!(vec![0, 251, 502, 753], pool.starts);
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    let mut ids: Vec<Num> = (0..300).map(|_| pool.request_id().unwrap()).collect();
+                    // return half of the ids, each goes back to its shard
+                    for id in ids.split_off(150) {
+                        assert_eq!(Ok(()), pool.return_id(id));
+                    }
+                    ids
+                })
+            })
+            .collect();
+        let held: Vec<Num> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        let unique: HashSet<Num> = held.iter().copied().collect();
+        assert_eq!(600, unique.len());
+        assert_eq!(403, pool.available());
+        for &id in &held {
+            assert!(!pool.shard(id).lock().unwrap().is_free(id));
+        }
+        assert_eq!(600, pool.used_count());
+        for (shard, start) in pool.shards.iter().zip(&pool.starts) {
+            let shard = shard.lock().unwrap();
+            assert_eq!(*start, shard.range.start);
+            assert!(shard.free_ranges().all(|range| range.start >= *start));
+        }
+        let pool = Arc::try_unwrap(pool).unwrap().into_inner();
+        assert_eq!(0..1003, std::ops::Range::from(pool.range));
+        assert_eq!(600, pool.used_count());
+        pool.assert_consistent();
+    }
+
+    #[test]
+    fn guards() {
+        let pool = Arc::new(SyncIdPool::with_shards(0..100, 4));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                thread::spawn(move || (0..25).map(|_| pool.acquire().unwrap()).collect::<Vec<_>>())
+            })
+            .collect();
+        let guards: Vec<SyncIdGuard> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        let ids: HashSet<Num> = guards.iter().map(|guard| guard.id()).collect();
+        assert_eq!(100, ids.len());
+        assert!(pool.acquire().is_none());
+        // guards can be dropped on other threads
+        thread::spawn(move || drop(guards)).join().unwrap();
+        assert_eq!(0, pool.used_count());
+        assert_eq!(1, Arc::strong_count(&pool));
+    }
+}
@@ -0,0 +1,206 @@
+//! Harness for recording and replaying operations on an
+//! [`IdPool`], and for property testing it with random
+//! operation sequences. Enabled with the `testing` feature, so
+//! that downstream crates can validate their integration the
+//! same way the crate validates itself.
+//!
+//! ```
+//! # use id_pool::IdPool;
+//! # use id_pool::testing::{property_test, OpRecorder};
+//! let ops = property_test(0..100, 42, 1000);
+//! let pool = OpRecorder::replay(IdPool::new_ranged(0..100), &ops);
+//! pool.assert_consistent();
+//! ```
+//!
+//! [`IdPool`]: ../struct.IdPool.html
+
+use std::collections::BTreeSet;
+
+use crate::{IdPool, Num, Op};
+
+/// Pool wrapper recording every operation performed on it, so
+/// that the sequence can be replayed later.
+#[derive(Debug, Clone)]
+pub struct OpRecorder {
+    pool: IdPool,
+    ops: Vec<Op>,
+}
+
+impl OpRecorder {
+    /// Creates a new `OpRecorder` recording operations on `pool`.
+    pub fn new(pool: IdPool) -> Self {
+        Self {
+            pool,
+            ops: Vec::new(),
+        }
+    }
+
+    /// Gets the underlying pool.
+    pub fn pool(&self) -> &IdPool {
+        &self.pool
+    }
+
+    /// Gets the operations recorded so far.
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// Requests an id from the pool, recording the request.
+    pub fn request_id(&mut self) -> Option<Num> {
+        self.ops.push(Op::Request);
+        self.pool.request_id()
+    }
+
+    /// Returns an id to the pool, recording the return.
+    pub fn return_id(&mut self, id: Num) -> Result<(), Num> {
+        self.ops.push(Op::Return(id));
+        self.pool.return_id(id)
+    }
+
+    /// Applies the operations to `pool` in order, returning
+    /// the resulting pool. Failing operations are applied as
+    /// well, and fail the same way they did when recorded.
+    ///
+    /// This is the pool [`IdPool::simulate`] reports the stats of.
+    ///
+    /// [`IdPool::simulate`]: ../struct.IdPool.html#method.simulate
+    pub fn replay(mut pool: IdPool, ops: &[Op]) -> IdPool {
+        pool.apply(ops);
+        pool
+    }
+}
+
+/// Xorshift generator driving [`property_test`], for generating
+/// reproducible operation sequences in tests of your own.
+///
+/// [`property_test`]: fn.property_test.html
+#[derive(Debug, Clone)]
+pub struct XorShift(u64);
+
+impl XorShift {
+    /// Creates a new generator from `seed`, deriving the state
+    /// with a splitmix64 step so that every seed gives its own
+    /// sequence. Xorshift needs a non-zero state, so a fixed one
+    /// is used in the unlikely case the step yields 0.
+    pub fn new(seed: u64) -> Self {
+        let mut state = seed;
+        match crate::splitmix64(&mut state) {
+            0 => XorShift(0x9e37_79b9_7f4a_7c15),
+            state => XorShift(state),
+        }
+    }
+
+    /// Advances the generator, returning the next value.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[cfg(feature = "rand_core")]
+impl rand_core::RngCore for XorShift {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        XorShift::next_u64(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Runs `steps` random operations against a pool over `range`,
+/// checking the pool against a simple model after each of them,
+/// and returns the recorded operations. The same seed always
+/// produces the same sequence.
+///
+/// Requests must hand out the lowest free id, returns must
+/// succeed exactly for allocated ids, and the pool must stay
+/// consistent throughout.
+///
+/// # Panics
+///
+/// Panics with a description of the failed check if the pool
+/// diverges from the model.
+pub fn property_test(range: std::ops::Range<Num>, seed: u64, steps: usize) -> Vec<Op> {
+    let mut rng = XorShift::new(seed);
+    let mut next = move || rng.next_u64();
+    let span = (range.end - range.start) as u64;
+    let mut model: BTreeSet<Num> = BTreeSet::new();
+    let mut recorder = OpRecorder::new(IdPool::new_ranged(range.clone()));
+    for step in 0..steps {
+        if next() % 5 < 3 {
+            let expected = range.clone().find(|id| !model.contains(id));
+            let id = recorder.request_id();
+            assert_eq!(expected, id, "step {}: unexpected id requested", step);
+            model.extend(id);
+        } else {
+            // mostly allocated ids, sometimes arbitrary ones
+            let id = match model.iter().nth(next() as usize % model.len().max(1)) {
+                Some(&id) if next() % 4 != 0 => id,
+                _ => range.start + (next() % span.max(1)) as Num,
+            };
+            let result = recorder.return_id(id);
+            let expected = if model.remove(&id) { Ok(()) } else { Err(id) };
+            assert_eq!(expected, result, "step {}: unexpected return result", step);
+        }
+        assert_eq!(
+            model.len(),
+            recorder.pool().used_count(),
+            "step {}: used count",
+            step
+        );
+        recorder.pool().assert_consistent();
+    }
+    recorder.ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distinct_seeds() {
+        let firsts: BTreeSet<u64> = (0..64).map(|seed| XorShift::new(seed).next_u64()).collect();
+        assert_eq!(64, firsts.len());
+    }
+
+    #[test]
+    fn record_replay() {
+        for seed in 0..8 {
+            let ops = property_test(0..64, seed, 5_000);
+            assert_eq!(5_000, ops.len());
+            assert_eq!(ops, property_test(0..64, seed, 5_000));
+            let mut recorder = OpRecorder::new(IdPool::new_ranged(0..64));
+            for &op in &ops {
+                match op {
+                    Op::Request => {
+                        recorder.request_id();
+                    }
+                    Op::Return(id) => {
+                        let _ = recorder.return_id(id);
+                    }
+                }
+            }
+            assert_eq!(ops, recorder.ops());
+            let replayed = OpRecorder::replay(IdPool::new_ranged(0..64), &ops);
+            replayed.assert_consistent();
+            assert_eq!(IdPool::new_ranged(0..64).simulate(&ops), replayed.stats());
+            assert_eq!(recorder.pool().used_count(), replayed.used_count());
+            assert_eq!(
+                recorder.pool().free_ranges().collect::<Vec<_>>(),
+                replayed.free_ranges().collect::<Vec<_>>()
+            );
+        }
+    }
+}
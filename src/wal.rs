@@ -0,0 +1,230 @@
+use std::io::{self, BufRead, Write};
+
+use crate::{IdPool, Num};
+
+/// Id pool appending a record of every allocation and return to
+/// a write-ahead log, so that its state can be recovered with
+/// [`IdPool::replay`] after a crash.
+///
+/// Records are text lines, `+id` for an allocated id and `-id`
+/// for a returned one. Only operations that succeed are logged.
+/// Each record is written ahead of the operation with a single
+/// `write_all` call. If that fails, the operation isn't applied
+/// and the error is returned, leaving the pool untouched.
+///
+/// Records only reach the log as soon as the writer passes them
+/// on. With a buffering writer, like `BufWriter`, call [`flush`]
+/// before an id is handed over to anything that has to survive
+/// a crash, since unflushed records are lost with it.
+///
+/// # Examples
+///
+/// ```
+/// # use id_pool::{IdPool, LoggedIdPool};
+/// let mut pool = LoggedIdPool::new(IdPool::new(), Vec::new());
+/// let id = pool.request_id().unwrap().unwrap();
+/// let (_, log) = pool.into_inner();
+/// let mut recovered = IdPool::new();
+/// recovered.replay(&log[..]).unwrap();
+/// assert!(!recovered.is_free(id));
+/// ```
+///
+/// [`IdPool::replay`]: struct.IdPool.html#method.replay
+/// [`flush`]: #method.flush
+#[derive(Debug)]
+pub struct LoggedIdPool<W> {
+    pool: IdPool,
+    log: W,
+}
+
+impl<W: Write> LoggedIdPool<W> {
+    /// Creates a new `LoggedIdPool`, logging the operations on
+    /// `pool` to `log`.
+    pub fn new(pool: IdPool, log: W) -> Self {
+        Self { pool, log }
+    }
+
+    /// Gets the underlying pool.
+    pub fn pool(&self) -> &IdPool {
+        &self.pool
+    }
+
+    /// Consumes the wrapper, returning the pool and the log.
+    pub fn into_inner(self) -> (IdPool, W) {
+        (self.pool, self.log)
+    }
+
+    /// Returns a new id or `None` if there are no free ids in
+    /// the pool, logging the allocation.
+    pub fn request_id(&mut self) -> io::Result<Option<Num>> {
+        // the lowest available id is the one the request hands out
+        if let Some(id) = self.pool.lowest_available() {
+            self.log.write_all(format!("+{}\n", id).as_bytes())?;
+        }
+        Ok(self.pool.request_id())
+    }
+
+    /// Returns an id to the pool or `Err(Num)` if the id is
+    /// already in the pool, logging the return.
+    pub fn return_id(&mut self, id: Num) -> io::Result<Result<(), Num>> {
+        // returning fails exactly for the ids that aren't allocated
+        if self.pool.check_allocated(id).is_err() {
+            return Ok(Err(id));
+        }
+        self.log.write_all(format!("-{}\n", id).as_bytes())?;
+        Ok(self.pool.return_id(id))
+    }
+
+    /// Flushes the log, passing all written records on to the
+    /// underlying storage.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.log.flush()
+    }
+}
+
+impl IdPool {
+    /// Applies the records of a write-ahead log written by a
+    /// [`LoggedIdPool`], in order. Replaying the full log into a
+    /// pool in the state the logged one started out in restores
+    /// the state of the logged pool.
+    ///
+    /// Returns an error with `io::ErrorKind::InvalidData` if a
+    /// record is malformed or doesn't apply to the pool, e.g.
+    /// allocating an id that isn't free. A final record missing
+    /// its line break was cut short by a crash while logging it,
+    /// so it's skipped, like the operation it would have logged.
+    ///
+    /// [`LoggedIdPool`]: struct.LoggedIdPool.html
+    pub fn replay<R: BufRead>(&mut self, mut log: R) -> io::Result<()> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if log.read_line(&mut line)? == 0 {
+                break;
+            }
+            let line = match line.strip_suffix('\n') {
+                Some(line) => line,
+                // truncated final record
+                None => break,
+            };
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, line.to_owned());
+            let parse = |id: &str| id.parse::<Num>().map_err(|_| invalid());
+            let applied = if let Some(id) = line.strip_prefix('+') {
+                self.allocate(parse(id)?)
+            } else if let Some(id) = line.strip_prefix('-') {
+                self.return_id(parse(id)?).is_ok()
+            } else {
+                false
+            };
+            if !applied {
+                return Err(invalid());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay() {
+        let mut pool = LoggedIdPool::new(IdPool::new_ranged(0..10), Vec::new());
+        for _ in 0..6 {
+            pool.request_id().unwrap();
+        }
+        assert_eq!(Ok(()), pool.return_id(2).unwrap());
+        assert_eq!(Ok(()), pool.return_id(4).unwrap());
+        assert_eq!(Err(4), pool.return_id(4).unwrap());
+        assert_eq!(Some(2), pool.request_id().unwrap());
+        let (pool, log) = pool.into_inner();
+        assert_eq!(
+            "+0\n+1\n+2\n+3\n+4\n+5\n-2\n-4\n+2\n",
+            String::from_utf8_lossy(&log)
+        );
+
+        let mut recovered = IdPool::new_ranged(0..10);
+        recovered.replay(&log[..]).unwrap();
+        assert_eq!(pool.used_count(), recovered.used_count());
+        assert_eq!(
+            pool.free_ranges().collect::<Vec<_>>(),
+            recovered.free_ranges().collect::<Vec<_>>()
+        );
+        recovered.assert_consistent();
+
+        // records that don't apply are rejected
+        let err = recovered.replay(&b"+1\n"[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(recovered.replay(&b"*1\n"[..]).is_err());
+        assert!(recovered.replay(&b"+\n"[..]).is_err());
+        assert!(recovered.replay("é1\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn replay_truncated() {
+        let mut pool = IdPool::new_ranged(0..10);
+        // the crash cut the record for 12 short
+        pool.replay(&b"+0\n+1\n-0\n+1"[..]).unwrap();
+        assert_eq!(1, pool.used_count());
+        assert!(pool.is_free(0));
+        assert!(!pool.is_free(1));
+    }
+
+    /// Writer keeping every write call apart.
+    #[derive(Default)]
+    struct Chunks(Vec<Vec<u8>>);
+
+    impl Write for Chunks {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn single_write_per_record() {
+        let mut pool = LoggedIdPool::new(IdPool::new_ranged(0..100), Chunks::default());
+        for _ in 0..12 {
+            pool.request_id().unwrap();
+        }
+        assert_eq!(Ok(()), pool.return_id(10).unwrap());
+        let (_, log) = pool.into_inner();
+        assert_eq!(13, log.0.len());
+        assert_eq!(b"+11\n", &log.0[11][..]);
+        assert_eq!(b"-10\n", &log.0[12][..]);
+    }
+
+    /// Writer failing every write.
+    struct Failing;
+
+    impl Write for Failing {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_write_leaves_pool() {
+        let mut inner = IdPool::new_ranged(0..10);
+        inner.request_id();
+        let (allocations, version) = (inner.total_allocations(), inner.version());
+        let mut pool = LoggedIdPool::new(inner, Failing);
+        assert!(pool.request_id().is_err());
+        assert!(pool.return_id(0).is_err());
+        assert_eq!(Ok(Err(5)), pool.return_id(5).map_err(|_| ()));
+        let pool = pool.pool();
+        assert_eq!(1, pool.used_count());
+        assert_eq!(allocations, pool.total_allocations());
+        assert_eq!(version, pool.version());
+        pool.assert_consistent();
+    }
+}